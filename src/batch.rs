@@ -0,0 +1,474 @@
+//! Module of the ``batch`` command.
+//!
+//! The command generates a report for each candidate listed in a manifest. The manifest is either
+//! a TOML file:
+//! ```toml
+//! # Optional defaults of all candidates
+//! responses = "odzivi.json"
+//! template = "mnenje.tex"
+//!
+//! [[candidate]]
+//! name = "Janez Novak"
+//! csv = ["novak_2023.csv", "novak_2024.csv"]
+//! output = "mnenja/novak"
+//! seed = 1234  # optional
+//! vars = { COURSE = "Elektrotehnika" }  # optional
+//! ```
+//! or a CSV file with the columns ``name``, ``csv``, ``output`` and the optional ``responses``, ``template``
+//! and ``seed``. Multiple CSV files of a candidate are separated by ``|`` in the CSV manifest.
+//! Relative paths inside the manifest are relative to the manifest's directory.
+//!
+//! The ``{CANDIDATE}`` variable (see [`crate::placeholders`]) is set to the candidate's name, unless it is
+//! given explicitly.
+
+use serde::Deserialize;
+
+use std::path::{Path, PathBuf};
+use std::fs;
+
+use crate::create::{self, CreateOptions, Document, OutputFormat, Report};
+use crate::error::{Error, Result};
+use crate::merge::{self, MergeOptions, MergeWeighting, StdMode};
+use crate::placeholders::{self, Variables};
+use crate::preproc::{self, SurveyExport, SurveyOptions};
+use crate::config;
+use crate::docx::DocxTemplate;
+use crate::diversity::UsageState;
+use crate::compiler::{self, CompileJob, CompileOptions, Compiler, Workers};
+
+
+/// Separator of multiple STUDIS CSV files in a CSV manifest.
+const C_CSV_FILES_SEPARATOR: char = '|';
+/// Variable that is set to the candidate's name.
+const C_CANDIDATE_VARIABLE: &str = "CANDIDATE";
+
+
+/// A single candidate of the batch manifest.
+#[derive(Clone, Debug)]
+pub struct BatchEntry {
+    /// Name of the candidate, as shown in the summary.
+    pub name: String,
+    /// STUDIS CSV files (or glob patterns) of the candidate. Multiple files are merged (see [`merge::merge_files`])
+    /// before generating the report.
+    pub csv_files: Vec<PathBuf>,
+    /// The responses file. When ``None``, the default of the manifest or [`BatchOptions::responses`] is used.
+    pub responses: Option<PathBuf>,
+    /// The LaTeX template. When ``None``, the default of the manifest or [`BatchOptions::template`] is used.
+    pub template: Option<PathBuf>,
+    /// Path of the output file.
+    pub output: PathBuf,
+    /// Seed of the response selection. Random when ``None``.
+    pub seed: Option<u64>,
+    /// User variables of the candidate. They take precedence over [`BatchOptions::variables`].
+    pub variables: Variables
+}
+
+
+/// List of candidates to generate reports for.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    /// The candidates, in order of the manifest file.
+    pub entries: Vec<BatchEntry>
+}
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlManifest {
+    responses: Option<PathBuf>,
+    template: Option<PathBuf>,
+    #[serde(default, rename = "candidate")]
+    candidates: Vec<TomlEntry>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlEntry {
+    name: String,
+    csv: TomlFiles,
+    responses: Option<PathBuf>,
+    template: Option<PathBuf>,
+    output: PathBuf,
+    seed: Option<u64>,
+    #[serde(default)]
+    vars: Variables
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TomlFiles {
+    One(PathBuf),
+    Many(Vec<PathBuf>)
+}
+
+#[derive(Deserialize)]
+struct CsvEntry {
+    name: String,
+    csv: String,
+    #[serde(default)]
+    responses: Option<PathBuf>,
+    #[serde(default)]
+    template: Option<PathBuf>,
+    output: PathBuf,
+    #[serde(default)]
+    seed: Option<u64>
+}
+
+
+impl Manifest {
+    /// Reads the manifest at ``filepath``. The format is chosen by the file extension (``.toml`` or ``.csv``).
+    /// Relative paths of the manifest are resolved against the manifest's directory.
+    pub fn from_file(filepath: &Path) -> Result<Self> {
+        let content = fs::read_to_string(filepath).map_err(|e| Error::io("could not read manifest", filepath, e))?;
+        let extension = filepath.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        let mut manifest = match extension.as_str() {
+            "toml" => Self::from_toml(&content)?,
+            "csv" => Self::from_csv(&content)?,
+            _ => return Err(Error::Manifest(format!(
+                "unknown manifest format of {} (expected a .toml or .csv file)", filepath.display()
+            )))
+        };
+
+        let root = filepath.parent().unwrap_or(Path::new(""));
+        for entry in &mut manifest.entries {
+            for file in entry.csv_files.iter_mut().chain(&mut entry.responses).chain(&mut entry.template) {
+                *file = root.join(&*file);
+            }
+            entry.output = root.join(&entry.output);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Parses a TOML manifest.
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let manifest: TomlManifest = toml::from_str(content).map_err(|e| Error::Manifest(e.to_string()))?;
+        let entries = manifest.candidates.into_iter().map(|entry| BatchEntry {
+            name: entry.name,
+            csv_files: match entry.csv {
+                TomlFiles::One(file) => vec![file],
+                TomlFiles::Many(files) => files
+            },
+            responses: entry.responses.or_else(|| manifest.responses.clone()),
+            template: entry.template.or_else(|| manifest.template.clone()),
+            output: entry.output,
+            seed: entry.seed,
+            variables: entry.vars
+        }).collect::<Vec<_>>();
+
+        for entry in &entries {
+            for name in entry.variables.keys() {
+                placeholders::validate_variable_name(name)
+                    .map_err(|e| Error::Manifest(format!("candidate \"{}\": {e}", entry.name)))?;
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Parses a CSV manifest.
+    pub fn from_csv(content: &str) -> Result<Self> {
+        let delimiter = preproc::get_delimiter(content).map_err(|_| Error::Manifest(
+            "could not detect the delimiter of the CSV manifest".to_string()
+        ))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter as u8)
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+
+        let mut entries = Vec::new();
+        for entry in reader.deserialize::<CsvEntry>() {
+            let entry = entry.map_err(|e| Error::Manifest(e.to_string()))?;
+            entries.push(BatchEntry {
+                name: entry.name,
+                csv_files: entry.csv.split(C_CSV_FILES_SEPARATOR)
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                    .map(PathBuf::from)
+                    .collect(),
+                responses: entry.responses,
+                template: entry.template,
+                output: entry.output,
+                seed: entry.seed,
+                variables: Variables::new()
+            });
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+
+/// Options of the ``batch`` command.
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// The responses file of entries that don't define their own.
+    pub responses: Option<PathBuf>,
+    /// The LaTeX template of entries that don't define their own.
+    pub template: Option<PathBuf>,
+    /// Name of the default grades section (see [`CreateOptions::section`]).
+    pub section: String,
+    /// The format of the output files.
+    pub format: OutputFormat,
+    /// Weighting of the means, when an entry has multiple STUDIS CSV files.
+    pub weighting: MergeWeighting,
+    /// Calculation of the standard deviation, when an entry has multiple STUDIS CSV files.
+    pub std_mode: StdMode,
+    /// User variables of all entries.
+    pub variables: Variables,
+    /// Minimum similarity of question texts for a fuzzy match (see [`CreateOptions::similarity`]).
+    pub similarity: f64,
+    /// State file of the used responses, shared by all entries (see [`CreateOptions::usage_filepath`]).
+    pub usage_filepath: Option<PathBuf>,
+    /// Options of reading the STUDIS exports (e.g., their encoding).
+    pub survey: SurveyOptions,
+    /// Options of the LaTeX compilation (PDF format only).
+    pub compile: CompileOptions,
+    /// Worker processes of concurrent LaTeX compilations (PDF format only, see [`compiler::compile_parallel`]).
+    /// When ``None``, the reports are compiled one by one in this process.
+    pub workers: Option<Workers>
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            responses: None,
+            template: None,
+            section: config::create::SECTION_DEFAULT.to_string(),
+            format: config::create::FORMAT_DEFAULT,
+            weighting: config::merge::WEIGHTING_DEFAULT,
+            std_mode: config::merge::STD_MODE_DEFAULT,
+            variables: Variables::new(),
+            similarity: config::create::SIMILARITY_DEFAULT,
+            usage_filepath: None,
+            survey: SurveyOptions::default(),
+            compile: CompileOptions::default(),
+            workers: None
+        }
+    }
+}
+
+
+/// The result of a single manifest entry.
+#[derive(Debug)]
+pub struct BatchOutcome {
+    /// Name of the candidate.
+    pub name: String,
+    /// The generated report or the reason of failure.
+    pub result: Result<Report>
+}
+
+
+/// Generates the report of a single manifest ``entry``, compiled (PDF only) with the ``compiler``.
+pub fn run_entry(entry: &BatchEntry, options: &BatchOptions, compiler: &mut Compiler) -> Result<Report> {
+    generate_entry(entry, options)?.finish(compiler)
+}
+
+
+/// Generates the report of a single manifest ``entry``, without compiling and writing it (see [`Document::finish`]).
+pub fn generate_entry(entry: &BatchEntry, options: &BatchOptions) -> Result<Document> {
+    let responses = entry.responses.as_ref().or(options.responses.as_ref())
+        .ok_or_else(|| Error::Input("no responses file is given (in the manifest or as an option)".to_string()))?;
+    let template = entry.template.as_ref().or(options.template.as_ref())
+        .ok_or_else(|| Error::Input("no template is given (in the manifest or as an option)".to_string()))?;
+    let mut variables = options.variables.clone();
+    variables.entry(C_CANDIDATE_VARIABLE.to_string()).or_insert_with(|| entry.name.clone());
+    variables.extend(entry.variables.clone());
+    let create_options = CreateOptions {
+        section: options.section.clone(),
+        format: options.format.clone(),
+        output_filepath: Some(entry.output.clone()),
+        seed: entry.seed,
+        variables,
+        similarity: options.similarity,
+        usage_filepath: options.usage_filepath.clone(),
+        survey: options.survey,
+        compile: options.compile.clone()
+    };
+
+    let files = merge::expand_patterns(&entry.csv_files)?;
+    match files.as_slice() {
+        [] => Err(Error::Input(format!("STUDIS CSV files {:?} matched no files", entry.csv_files))),
+        [file] => create::generate_document(&SurveyExport::from_file(file, &options.survey)?, responses, template, &create_options),
+        files => {
+            // Merge the sections used by the template into a single STUDIS export.
+            let mut survey = SurveyExport::default();
+            let template_text = match options.format {
+                OutputFormat::Docx => DocxTemplate::open(template)?.text(),
+                _ => create::load_template(template)?
+            };
+            for placeholder in create::template_placeholders(&template_text) {
+                let section = placeholder.section.unwrap_or_else(|| options.section.clone());
+                if survey.section(&section).is_ok() {
+                    continue;
+                }
+                let merge_options = MergeOptions {
+                    section: section.clone(),
+                    weighting: options.weighting,
+                    std_mode: options.std_mode,
+                    similarity: options.similarity,
+                    survey: options.survey
+                };
+                survey.sections.push(merge::merged_section(&section, &merge::merge_files(files, &merge_options)?.questions));
+            }
+            create::generate_document(&survey, responses, template, &create_options)
+        }
+    }
+}
+
+
+/// Command processing function for the ``batch`` command.
+/// Generates the reports of all the entries of the ``manifest`` file, continuing past failed entries.
+/// ``on_done`` is called after each entry is processed, with the number of processed entries and the number of all entries.
+/// The outcomes are returned in the order of the manifest.
+///
+/// PDF reports are compiled concurrently by the [`BatchOptions::workers`] (see [`compiler::compile_parallel`]),
+/// after all the reports are generated. The used responses (see [`BatchOptions::usage_filepath`]) are then tracked
+/// in a copy of the state file while generating, and added to the state file once a report is compiled and written.
+pub fn command_batch(
+    manifest: &Path,
+    options: &BatchOptions,
+    mut on_done: impl FnMut(&BatchOutcome, usize, usize)
+) -> Result<Vec<BatchOutcome>> {
+    let manifest = Manifest::from_file(manifest)?;
+    let total = manifest.entries.len();
+    let mut outcomes: Vec<Option<BatchOutcome>> = (0..total).map(|_| None).collect();
+    let mut finish = |i: usize, result: Result<Report>| {
+        let outcome = BatchOutcome { name: manifest.entries[i].name.clone(), result };
+        let done = outcomes.iter().filter(|o| o.is_some()).count() + 1;
+        on_done(&outcome, done, total);
+        outcomes[i] = Some(outcome);
+    };
+
+    if options.workers.as_ref().is_none_or(|w| w.count <= 1) || options.format != OutputFormat::Pdf {
+        let mut compiler = Compiler::new(options.compile.clone());
+        for (i, entry) in manifest.entries.iter().enumerate() {
+            finish(i, run_entry(entry, options, &mut compiler));
+        }
+    }
+    else {
+        let directory = tempfile::tempdir()
+            .map_err(|e| Error::io("could not create temporary directory", std::env::temp_dir(), e))?;
+        let mut generation = options.clone();
+        if let Some(filepath) = &options.usage_filepath {
+            let session = directory.path().join("usage.json");
+            UsageState::load(filepath)?.save(&session)?;
+            generation.usage_filepath = Some(session);
+        }
+
+        // The job of each document is at the document's index
+        let mut documents = Vec::new();
+        let mut jobs: Vec<CompileJob> = Vec::new();
+        for (i, entry) in manifest.entries.iter().enumerate() {
+            let generated = generate_entry(entry, &generation).and_then(|mut document| {
+                // The next reports see this one's responses, which reach the state file only once it is written
+                if let (Some((filepath, picks)), Some(state)) = (&mut document.usage, &options.usage_filepath) {
+                    picks.add_to(filepath)?;
+                    *filepath = state.clone();
+                }
+                Ok(document)
+            });
+            match generated {
+                Ok(document) => match document.latex.clone() {
+                    Some(job) => {
+                        jobs.push(job);
+                        documents.push((i, Some(document)));
+                    }
+                    None => finish(i, document.write(None))
+                },
+                Err(e) => finish(i, Err(e))
+            }
+        }
+
+        compiler::compile_parallel(&jobs, options.workers.as_ref(), &options.compile, |j, result| {
+            let (i, document) = &mut documents[j];
+            let document = document.take().expect("each document is compiled once");
+            finish(*i, result.map_err(|e| document.locate_errors(e)).and_then(|pdf| document.write(Some(pdf))));
+        })?;
+    }
+
+    Ok(outcomes.into_iter().flatten().collect())
+}
+
+
+/// Formats the ``outcomes`` as a table of candidates, their status, seed and output file (or error).
+pub fn summary_table(outcomes: &[BatchOutcome]) -> String {
+    let header = ["#", "Candidate", "Status", "Seed", "Output / error"];
+    let rows: Vec<[String; 5]> = outcomes.iter().enumerate().map(|(i, outcome)| match &outcome.result {
+        Ok(report) => [
+            (i + 1).to_string(),
+            outcome.name.clone(),
+            "ok".to_string(),
+            report.seed.to_string(),
+            report.path.display().to_string()
+        ],
+        Err(error) => [
+            (i + 1).to_string(),
+            outcome.name.clone(),
+            "FAILED".to_string(),
+            String::new(),
+            error_chain(error)
+        ]
+    }).collect();
+
+    let mut widths = header.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[&str]| {
+        let line = cells.iter().zip(widths).map(|(cell, width)| format!("{cell:width$}")).collect::<Vec<_>>();
+        line.join("  ").trim_end().to_string()
+    };
+    let mut table = vec![
+        format_row(&header),
+        format_row(&widths.map(|w| "-".repeat(w)).each_ref().map(String::as_str))
+    ];
+    table.extend(rows.iter().map(|row| format_row(&row.each_ref().map(String::as_str))));
+    table.join("\n")
+}
+
+
+/// Formats the ``error`` and its causes on a single line.
+fn error_chain(error: &Error) -> String {
+    let mut chain = vec![error.to_string()];
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        chain.push(cause.to_string());
+        source = cause.source();
+    }
+    chain.join(": ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::docx;
+
+    #[test]
+    fn merges_surveys_for_docx() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let directory = tempfile::tempdir().unwrap();
+        let path = |name: &str| directory.path().join(name);
+        fs::copy(root.join("anketa.csv"), path("a.csv")).unwrap();
+        fs::copy(root.join("anketa.csv"), path("b.csv")).unwrap();
+        fs::write(path("mnenje.docx"), docx::tests::archive(
+            "<w:p><w:r><w:t>Mnenje za {CANDIDATE}</w:t></w:r></w:p><w:p><w:r><w:t>{AUTO_GEN}</w:t></w:r></w:p>"
+        )).unwrap();
+        fs::write(path("manifest.toml"), format!(
+            "responses = {:?}\ntemplate = \"mnenje.docx\"\n\n\
+            [[candidate]]\nname = \"Janez Novak\"\ncsv = [\"a.csv\", \"b.csv\"]\noutput = \"novak.docx\"\nseed = 1\n",
+            root.join("odzivi.json")
+        )).unwrap();
+
+        let options = BatchOptions { format: OutputFormat::Docx, ..Default::default() };
+        let outcomes = command_batch(&path("manifest.toml"), &options, |_, _, _| ()).unwrap();
+        let report = outcomes[0].result.as_ref().unwrap();
+        let document = docx::tests::document(&fs::read(&report.path).unwrap());
+        assert!(document.contains("Mnenje za Janez Novak"));
+        assert!(!document.contains("{AUTO_GEN}"));
+    }
+}
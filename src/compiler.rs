@@ -1,50 +1,630 @@
+//! LaTeX compilation through the Tectonic engine.
+
 use tectonic::driver::{OutputFormat, ProcessingSessionBuilder};
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::config::PersistentConfig;
-use tectonic::status::ChatterLevel;
-
+use tectonic::status::{ChatterLevel, StatusBackend};
+use tectonic::io::{digest, InputHandle, InputOrigin, IoProvider, OpenResult};
+use tectonic::io::memory::MemoryFileCollection;
+use tectonic_bundles::Bundle;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
 
-use std::fs::{read_to_string, File};
-use std::time::SystemTime;
-use std::path::PathBuf;
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::fs::{self, read_to_string, File};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::io::{self, Cursor, Read, Write};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 use std::env;
+use std::fmt;
+
+use crate::error::{Error, Result};
+use crate::placeholders::{self, Variables};
+
+
+/// Name of the primary input file of the compilation.
+const INPUT_NAME: &str = "texput.tex";
+/// Name of the log file of the compilation.
+const LOG_NAME: &str = "texput.log";
+/// Name of the output file of the compilation.
+const OUTPUT_NAME: &str = "texput.pdf";
+
+/// Name of the (hidden) CLI command, which compiles a single document in a worker process (see [`compile_parallel`]).
+pub const WORKER_COMMAND: &str = "compile-worker";
+
+
+/// Options of the LaTeX compilation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// Local bundle of TeX support files (packages, fonts, ...): a directory, a ZIP file or a tar archive
+    /// (e.g., exported by [`command_bundle`]). When ``None``, the default bundle is used, whose files are
+    /// downloaded on first use and cached.
+    pub bundle: Option<PathBuf>,
+    /// Use only the cached files of the default bundle, without network access.
+    pub offline: bool
+}
+
+
+/// Worker processes of concurrent compilations (see [`compile_parallel`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workers {
+    /// Executable that compiles a document when started with the [`WORKER_COMMAND`] (see [`command_compile_worker`]),
+    /// i.e., the ``cloggen`` command line program.
+    pub executable: PathBuf,
+    /// Maximum number of concurrent worker processes.
+    pub count: usize
+}
+
+
+/// A LaTeX document to compile.
+#[derive(Clone, Debug)]
+pub struct CompileJob {
+    /// The LaTeX source.
+    pub latex: String,
+    /// The directory of the files referenced by the source (see [`source_root`]).
+    pub root: PathBuf
+}
 
-use crate::with_parent_path;
+
+/// A LaTeX compiler for repeated compilations in the same process (e.g., a batch of reports).
+///
+/// The bundle of TeX support files and the location of the format cache are resolved by the first compilation
+/// and kept for the next ones, which then only run the TeX engine. The compiler is not [`Send`]
+/// (neither are the bundles), so it must stay in the thread that created it.
+pub struct Compiler {
+    options: CompileOptions,
+    /// The opened bundle and the format cache path. ``None`` before the first compilation.
+    state: Option<(SharedBundle, PathBuf)>
+}
+
+
+impl Compiler {
+    /// Creates a compiler with the ``options``. The bundle is opened on the first compilation.
+    pub fn new(options: CompileOptions) -> Self {
+        Self { options, state: None }
+    }
+
+    /// The options of the compiler.
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    /// Compiles the ``latex`` source into a PDF file, like [`compile_latex`].
+    pub fn compile(&mut self, latex: &str, root: &Path) -> Result<Vec<u8>> {
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Normal);
+        let (bundle, format_cache_path) = match &self.state {
+            Some(state) => state,
+            None => {
+                let config = PersistentConfig::open(false).map_err(|e| Error::compile(e.into(), String::new()))?;
+                let bundle = open_bundle(&config, &self.options, &mut status)?;
+                let format_cache_path = config.format_cache_path().map_err(|e| Error::compile(e.into(), String::new()))?;
+                self.state.insert((SharedBundle(Rc::new(RefCell::new(bundle))), format_cache_path))
+            }
+        };
+
+        let (result, mut files) = run_session(latex, root, Box::new(bundle.clone()), format_cache_path, &mut status);
+        if let Err(e) = result {
+            let log = session_log(&files);
+            return Err(missing_file_error(&log, &self.options).unwrap_or_else(|| Error::compile(e, log)));
+        }
+        Ok(files.remove(OUTPUT_NAME).expect("compilation was successful but file data was not created").data)
+    }
+}
 
 
 /// Modification of [`tectonic::latex_to_pdf`] which adds stdout print to the console.
-pub fn compile_latex(latex: impl AsRef<str>) -> std::io::Result<Vec<u8>> {
-    let mut status = TermcolorStatusBackend::new(ChatterLevel::Normal);
-    let config = PersistentConfig::open(false)?;
-    let bundle = config.default_bundle(false, &mut status)?;
-    let mut files = {
-        let mut sess;
-        let mut sb = ProcessingSessionBuilder::default();
-        let format_cache_path = config.format_cache_path()?;
-        sb.bundle(bundle)
-            .primary_input_buffer(latex.as_ref().as_bytes())
-            .tex_input_name("texput.tex")
-            .format_name("latex")
-            .format_cache_path(format_cache_path)
-            .keep_logs(false)
-            .keep_intermediates(false)
-            .print_stdout(false)
-            .output_format(OutputFormat::Pdf)
-            .build_date(SystemTime::now())
-            .do_not_write_output_files();
-        sess = sb.create(&mut status)?;
-        sess.run(&mut status)?;        
-        sess.into_file_data()
+/// Files referenced by the ``latex`` source (e.g., ``\input`` or ``\includegraphics``) are resolved relative to
+/// the ``root`` directory (see [`source_root`]). The working directory is not changed, so compilations
+/// of different threads do not interfere. Use a [`Compiler`] for repeated compilations.
+pub fn compile_latex(latex: impl AsRef<str>, root: &Path, options: &CompileOptions) -> Result<Vec<u8>> {
+    Compiler::new(options.clone()).compile(latex.as_ref(), root)
+}
+
+
+/// Runs the TeX engine on the ``latex`` source, with files on disk resolved relative to ``root``.
+/// Returns the result and the files of the session, which include the log (see [`session_log`]).
+fn run_session(
+    latex: &str,
+    root: &Path,
+    bundle: Box<dyn Bundle>,
+    format_cache_path: &Path,
+    status: &mut dyn StatusBackend
+) -> (io::Result<()>, MemoryFileCollection) {
+    let mut sb = ProcessingSessionBuilder::default();
+    sb.bundle(bundle)
+        .primary_input_buffer(latex.as_bytes())
+        .tex_input_name(INPUT_NAME)
+        .filesystem_root(root)
+        .format_name("latex")
+        .format_cache_path(format_cache_path)
+        // The log is still kept in the session's files (see ``session_log``), it is only not written to disk
+        .keep_logs(false)
+        .keep_intermediates(false)
+        .print_stdout(false)
+        .output_format(OutputFormat::Pdf)
+        .build_date(SystemTime::now())
+        .do_not_write_output_files();
+
+    let mut sess = match sb.create(status) {
+        Ok(sess) => sess,
+        Err(e) => return (Err(e.into()), MemoryFileCollection::new())
+    };
+    let result = sess.run(status).map_err(io::Error::from);
+    (result, sess.into_file_data())
+}
+
+
+/// Compiles the ``jobs`` concurrently, by the ``workers``. ``on_done`` is called with the index
+/// of each job and the compiled PDF (or the error), in the order in which the compilations finish.
+///
+/// Tectonic runs a single TeX engine per process, so the concurrent compilations run in worker processes
+/// (see [`Workers::executable`]). Without ``workers``, the jobs are compiled one by one in this process.
+/// The first job is always compiled in this process, which generates the format and downloads
+/// the bundle's files once, so that the workers reuse them from the cache.
+pub fn compile_parallel(
+    jobs: &[CompileJob],
+    workers: Option<&Workers>,
+    options: &CompileOptions,
+    mut on_done: impl FnMut(usize, Result<Vec<u8>>)
+) -> Result<()> {
+    let Some(first) = jobs.first() else {
+        return Ok(());
+    };
+    let mut compiler = Compiler::new(options.clone());
+    on_done(0, compiler.compile(&first.latex, &first.root));
+    let Some(Workers { executable, count }) = workers.filter(|w| w.count > 1) else {
+        for (i, job) in jobs.iter().enumerate().skip(1) {
+            on_done(i, compiler.compile(&job.latex, &job.root));
+        }
+        return Ok(());
+    };
+
+    let directory = tempfile::tempdir()
+        .map_err(|e| Error::io("could not create temporary directory", env::temp_dir(), e))?;
+    let next = AtomicUsize::new(1);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..(*count).min(jobs.len() - 1) {
+            let sender = sender.clone();
+            let (next, directory) = (&next, directory.path());
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(i) else {
+                        break;
+                    };
+                    let result = compile_in_worker(executable, &directory.join(format!("{i}.tex")), job, options);
+                    if sender.send((i, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (i, result) in receiver {
+            on_done(i, result);
+        }
+    });
+
+    Ok(())
+}
+
+
+/// Compiles the ``job`` in a worker process of the ``executable``, through the LaTeX file at ``path``.
+fn compile_in_worker(executable: &Path, path: &Path, job: &CompileJob, options: &CompileOptions) -> Result<Vec<u8>> {
+    fs::write(path, &job.latex).map_err(|e| Error::io("could not write LaTeX file", path, e))?;
+    let root = if job.root.as_os_str().is_empty() { Path::new(".") } else { &job.root };
+
+    let mut command = Command::new(executable);
+    command.arg(WORKER_COMMAND).arg(path).arg("--root").arg(root);
+    if let Some(bundle) = &options.bundle {
+        command.arg("--bundle").arg(bundle);
+    }
+    if options.offline {
+        command.arg("--offline");
+    }
+    let output = command.stdin(Stdio::null()).output()
+        .map_err(|e| Error::io("could not run compile worker", executable, e))?;
+
+    if output.status.success() {
+        let pdf = worker_output_path(path);
+        return fs::read(&pdf).map_err(|e| Error::io("could not read PDF file", pdf, e));
+    }
+
+    // The last line of the printed error is its innermost cause (``error: ...`` or ``caused by: ...``)
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let last_line = stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or("compile worker failed");
+    let message = last_line.strip_prefix("caused by: ").or_else(|| last_line.strip_prefix("error: ")).unwrap_or(last_line);
+    let log = fs::read_to_string(worker_log_path(path)).unwrap_or_default();
+    Err(missing_file_error(&log, options).unwrap_or_else(|| Error::compile(io::Error::other(message.to_string()), log)))
+}
+
+
+/// Compiles the LaTeX file at ``path`` for [`compile_parallel`], with files resolved relative to ``root``.
+/// The PDF is saved as ``<path>.pdf`` and, when the compilation fails, the TeX log as ``<path>.log``.
+pub fn command_compile_worker(path: &Path, root: &Path, options: &CompileOptions) -> Result<()> {
+    let fdata = read_to_string(path).map_err(|e| Error::io("could not read LaTeX file", path, e))?;
+    let compiled = compile_latex(fdata, root, options).inspect_err(|e| {
+        if let Error::Compile { log, .. } = e {
+            let _ = fs::write(worker_log_path(path), log);
+        }
+    })?;
+
+    let output = worker_output_path(path);
+    fs::write(&output, compiled).map_err(|e| Error::io("could not write PDF file", &output, e))
+}
+
+
+fn worker_output_path(path: &Path) -> PathBuf {
+    PathBuf::from(path.display().to_string() + ".pdf")
+}
+
+
+fn worker_log_path(path: &Path) -> PathBuf {
+    PathBuf::from(path.display().to_string() + ".log")
+}
+
+
+/// Returns the directory of the LaTeX file at ``path``, which contains the files it references.
+pub fn source_root(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+
+/// Opens the bundle of TeX support files, selected by the ``options``.
+fn open_bundle(config: &PersistentConfig, options: &CompileOptions, status: &mut dyn StatusBackend) -> Result<Box<dyn Bundle>> {
+    let Some(path) = &options.bundle else {
+        return config.default_bundle(options.offline, status).map_err(|e| Error::Bundle(format!(
+            "could not open the default bundle{}: {e}",
+            if options.offline { " for offline use (was it downloaded with `cloggen bundle`?)" } else { "" }
+        )));
     };
-    Ok(files.remove("texput.pdf").expect("compilation was successful but file data was not created").data)
+
+    if !path.exists() {
+        return Err(Error::Bundle(format!("bundle {} does not exist", path.display())));
+    }
+    let path = if is_tar(path) { extract_tar(path)? } else { path.clone() };
+    config.make_local_file_provider(path.clone(), status)
+        .map_err(|e| Error::Bundle(format!("could not open bundle {}: {e}", path.display())))
+}
+
+
+/// Returns whether the bundle at ``path`` is a tar archive.
+fn is_tar(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|e| e.eq_ignore_ascii_case("tar"))
+}
+
+
+/// Extracts the tar bundle at ``path`` (once) and returns the directory of its files.
+/// The directory is reused, until the archive is modified.
+fn extract_tar(path: &Path) -> Result<PathBuf> {
+    let metadata = fs::metadata(path).map_err(|e| Error::io("could not read bundle", path, e))?;
+    let modified = metadata.modified().ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |m| m.as_secs());
+    let stem = path.file_stem().map_or("bundle".into(), |s| s.to_string_lossy());
+    let directory = env::temp_dir()
+        .join("cloggen-bundles")
+        .join(format!("{stem}-{}-{modified}", metadata.len()));
+    if directory.is_dir() {
+        return Ok(directory);
+    }
+
+    // Extract into a temporary directory first, so that an interrupted extraction is not reused
+    let partial = directory.with_extension("partial");
+    let file = File::open(path).map_err(|e| Error::io("could not read bundle", path, e))?;
+    let _ = fs::remove_dir_all(&partial);
+    tar::Archive::new(file).unpack(&partial).map_err(|e| Error::io("could not extract bundle", path, e))?;
+    fs::rename(&partial, &directory).map_err(|e| Error::io("could not extract bundle", &directory, e))?;
+    Ok(directory)
+}
+
+
+/// Returns the log of the compilation, whose ``files`` are returned by [`run_session`].
+fn session_log(files: &MemoryFileCollection) -> String {
+    files.get(LOG_NAME).map(|f| String::from_utf8_lossy(&f.data).into_owned()).unwrap_or_default()
+}
+
+
+/// An error reported in the TeX log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TexError {
+    /// The error message (e.g., ``Misplaced alignment tab character &.``).
+    pub message: String,
+    /// Line of the LaTeX source where TeX detected the error, if known.
+    pub line: Option<usize>,
+    /// The source text that TeX read up to the error.
+    pub context: String,
+    /// Description of the line's origin (e.g., the response that produced it), if known.
+    pub origin: Option<String>
+}
+
+impl fmt::Display for TexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message)?,
+            None => write!(f, "{}", self.message)?
+        }
+        if !self.context.is_empty() {
+            write!(f, " (after \"{}\")", self.context)?;
+        }
+        if let Some(origin) = &self.origin {
+            write!(f, "\n    in {origin}")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Parses the errors of a TeX ``log``. An error starts with a line beginning with ``!`` and is (usually)
+/// followed by its location, e.g., ``l.12 Ocena je 50 &``.
+pub fn parse_log(log: &str) -> Vec<TexError> {
+    let mut errors: Vec<TexError> = Vec::new();
+    let mut located = true;
+    for line in log.lines() {
+        if let Some(message) = line.strip_prefix("! ") {
+            errors.push(TexError { message: message.trim().to_string(), line: None, context: String::new(), origin: None });
+            located = false;
+        }
+        else if !located
+            && let Some(location) = line.strip_prefix("l.")
+            && let Some(error) = errors.last_mut()
+        {
+            let (number, context) = location.split_once(' ').unwrap_or((location, ""));
+            if let Ok(number) = number.parse() {
+                error.line = Some(number);
+                error.context = context.trim().to_string();
+                located = true;
+            }
+        }
+    }
+    errors
+}
+
+
+/// Returns an error naming the TeX files that the compilation did not find (according to its ``log``).
+/// Returns ``None`` when no file is missing.
+fn missing_file_error(log: &str, options: &CompileOptions) -> Option<Error> {
+    let missing = missing_files(log);
+    if missing.is_empty() {
+        return None;
+    }
+
+    let missing = missing.iter().map(|f| format!("`{f}`")).collect::<Vec<_>>().join(", ");
+    Some(Error::Bundle(match (&options.bundle, options.offline) {
+        (Some(bundle), _) => format!(
+            "{missing} not found in bundle {}. Export a bundle that includes it with `cloggen bundle`.",
+            bundle.display()
+        ),
+        (None, true) => format!(
+            "{missing} not found in the offline cache. Download it with `cloggen bundle` while online."
+        ),
+        (None, false) => format!("{missing} not found")
+    }))
+}
+
+
+/// Returns the names of the files that TeX did not find, according to its ``log``.
+fn missing_files(log: &str) -> Vec<String> {
+    let mut missing = Vec::new();
+    for (prefix, suffix) in [("File `", "' not found"), ("I can't find file `", "'")] {
+        let mut rest = log;
+        while let Some(start) = rest.find(prefix) {
+            rest = &rest[start + prefix.len()..];
+            if let Some(end) = rest.find(suffix) && !rest[..end].contains('\n') {
+                let name = rest[..end].to_string();
+                if !missing.contains(&name) {
+                    missing.push(name);
+                }
+            }
+        }
+    }
+    missing
+}
+
+
+/// Compiles the LaTeX file at ``path`` into a PDF file, saved next to it (``<path>.pdf``).
+/// Returns the path of the PDF file.
+pub fn command_compile(path: &Path, options: &CompileOptions) -> Result<PathBuf> {
+    let fdata = read_to_string(path).map_err(|e| Error::io("could not read LaTeX file", path, e))?;
+    let compiled = compile_latex(fdata, source_root(path), options)?;
+    let output = PathBuf::from(path.display().to_string() + ".pdf");
+    let mut file = File::create(&output).map_err(|e| Error::io("could not create PDF file", &output, e))?;
+    file.write_all(&compiled).map_err(|e| Error::io("could not write PDF file", &output, e))?;
+    Ok(output)
 }
 
 
-pub fn cmd_compile(path: &PathBuf) -> std::io::Result<()> {
-    let fdata = read_to_string(path)?;
-    let compiled = with_parent_path!(path, {compile_latex(fdata)?});
-    let mut file = File::create(path.display().to_string() + ".pdf")?;
-    file.write_all(&compiled)?;
+/// Compiles the LaTeX files at ``paths`` (e.g., the report templates) with the default bundle, downloading the
+/// TeX support files they use, and exports these files as a bundle to ``output``: a directory, a ``.zip`` file or
+/// a ``.tar`` archive. The built-in placeholders (e.g., ``{AUTO_GEN}``, see [`placeholders::RESERVED`]) and the user
+/// ``variables`` are removed from the files before compilation, other brace groups (e.g., ``\ref{FIG1}``) are kept.
+/// The exported bundle can be used offline through [`CompileOptions::bundle`]. Returns the number of exported files.
+pub fn command_bundle(paths: &[PathBuf], variables: &Variables, output: &Path) -> Result<usize> {
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Normal);
+    let config = PersistentConfig::open(false).map_err(|e| Error::compile(e.into(), String::new()))?;
+    let bundle_error = |e: &dyn std::fmt::Display| Error::Bundle(format!("could not open the default bundle: {e}"));
+    let digest = config.default_bundle(false, &mut status)
+        .map_err(|e| bundle_error(&e))?
+        .get_digest()
+        .map_err(|e| bundle_error(&e))?;
+
+    // The digest identifies the bundle's formats in the format cache
+    let files = Rc::new(RefCell::new(BTreeMap::new()));
+    files.borrow_mut().insert(digest::DIGEST_NAME.to_string(), digest.to_string().into_bytes());
+
+    // Use an empty format cache, so that the files used to generate the format are exported as well
+    let format_cache = tempfile::tempdir()
+        .map_err(|e| Error::io("could not create temporary directory", env::temp_dir(), e))?;
+    for path in paths {
+        let latex = read_to_string(path).map_err(|e| Error::io("could not read LaTeX file", path, e))?;
+        let latex = placeholders::substitute(&latex, |name, _| Ok(
+            (placeholders::RESERVED.contains(&name) || variables.contains_key(name)).then(String::new)
+        ))?;
+        let bundle = RecordingBundle {
+            inner: config.default_bundle(false, &mut status).map_err(|e| bundle_error(&e))?,
+            files: files.clone()
+        };
+        let (result, session_files) = run_session(&latex, source_root(path), Box::new(bundle), format_cache.path(), &mut status);
+        if let Err(e) = result {
+            let log = session_log(&session_files);
+            return Err(missing_file_error(&log, &CompileOptions::default()).unwrap_or_else(|| Error::compile(e, log)));
+        }
+    }
+
+    let files = files.borrow();
+    write_bundle(&files, output).map_err(|e| Error::io("could not write bundle", output, e))?;
+    Ok(files.len())
+}
+
+
+/// Writes the bundle ``files`` to ``output``: a ``.zip`` file, a ``.tar`` archive or a directory.
+fn write_bundle(files: &BTreeMap<String, Vec<u8>>, output: &Path) -> io::Result<()> {
+    let extension = output.extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("zip") => {
+            let mut writer = ZipWriter::new(File::create(output)?);
+            for (name, data) in files {
+                writer.start_file(name, SimpleFileOptions::default())?;
+                writer.write_all(data)?;
+            }
+            writer.finish()?;
+        },
+        Some("tar") => {
+            let mut builder = tar::Builder::new(File::create(output)?);
+            for (name, data) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append_data(&mut header, name, data.as_slice())?;
+            }
+            builder.finish()?;
+        },
+        _ => {
+            for (name, data) in files {
+                let path = output.join(name);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, data)?;
+            }
+        }
+    }
     Ok(())
 }
+
+
+/// A bundle shared by the sessions of a [`Compiler`].
+#[derive(Clone)]
+struct SharedBundle(Rc<RefCell<Box<dyn Bundle>>>);
+
+impl IoProvider for SharedBundle {
+    fn input_open_name(&mut self, name: &str, status: &mut dyn StatusBackend) -> OpenResult<InputHandle> {
+        self.0.borrow_mut().input_open_name(name, status)
+    }
+}
+
+impl Bundle for SharedBundle {
+    fn get_digest(&mut self) -> anyhow::Result<digest::DigestData> {
+        self.0.borrow_mut().get_digest()
+    }
+
+    fn all_files(&self) -> Vec<String> {
+        self.0.borrow().all_files()
+    }
+}
+
+
+/// A bundle that keeps a copy of the files read from it.
+struct RecordingBundle {
+    inner: Box<dyn Bundle>,
+    files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>
+}
+
+impl IoProvider for RecordingBundle {
+    fn input_open_name(&mut self, name: &str, status: &mut dyn StatusBackend) -> OpenResult<InputHandle> {
+        match self.inner.input_open_name(name, status) {
+            OpenResult::Ok(mut handle) => {
+                let mut data = Vec::new();
+                if let Err(e) = handle.read_to_end(&mut data) {
+                    return OpenResult::Err(e.into());
+                }
+                self.files.borrow_mut().insert(name.to_string(), data.clone());
+                OpenResult::Ok(InputHandle::new_read_only(name, Cursor::new(data), InputOrigin::Other))
+            },
+            other => other
+        }
+    }
+}
+
+impl Bundle for RecordingBundle {
+    fn get_digest(&mut self) -> anyhow::Result<digest::DigestData> {
+        self.inner.get_digest()
+    }
+
+    fn all_files(&self) -> Vec<String> {
+        self.inner.all_files()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ERRORS_LOG: &str = include_str!("../tests/fixtures/tex_errors.log");
+    const MISSING_FILE_LOG: &str = include_str!("../tests/fixtures/tex_missing_file.log");
+
+    #[test]
+    fn parses_log() {
+        let errors = parse_log(ERRORS_LOG);
+        assert_eq!(errors, [
+            TexError {
+                message: "Misplaced alignment tab character &.".to_string(),
+                line: Some(12),
+                context: "Povprečna ocena je 4,5 &".to_string(),
+                origin: None
+            },
+            TexError {
+                message: "Undefined control sequence.".to_string(),
+                line: Some(15),
+                context: "\\textbff".to_string(),
+                origin: None
+            }
+        ]);
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        assert!(missing_file_error(ERRORS_LOG, &CompileOptions::default()).is_none());
+
+        let options = CompileOptions { bundle: None, offline: true };
+        match missing_file_error(MISSING_FILE_LOG, &options) {
+            Some(Error::Bundle(message)) => assert!(message.starts_with("`tikz-cd.sty` not found in the offline cache")),
+            error => panic!("expected a missing file error, got {error:?}")
+        }
+    }
+
+    /// Needs the TeX bundle (the network or a cached bundle), so run it with ``cargo test -- --ignored``.
+    #[test]
+    #[ignore]
+    fn keeps_log_of_failed_compilation() {
+        let latex = "\\documentclass{article}\n\\begin{document}\n\\textbff{x}\n\\end{document}\n";
+        match compile_latex(latex, Path::new("."), &CompileOptions::default()) {
+            Err(Error::Compile { errors, log, .. }) => {
+                assert!(log.contains("Undefined control sequence"));
+                assert_eq!(errors[0].line, Some(3));
+            }
+            result => panic!("expected a compile error, got {result:?}")
+        }
+    }
+}
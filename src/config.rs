@@ -11,13 +11,44 @@ pub mod create {
     /// Default output format.
     pub const FORMAT_DEFAULT: OutputFormat = OutputFormat::Pdf;
     pub const FORMAT_DEFAULT_STR: &str = "pdf";
+
+    /// Default minimum similarity of question texts for a fuzzy match.
+    pub const SIMILARITY_DEFAULT: f64 = 0.9;
+    pub const SIMILARITY_DEFAULT_STR: &str = "0.9";
+}
+
+/// Constants used for the Batch command.
+pub mod batch {
+    /// Default number of concurrent LaTeX compilations.
+    pub const JOBS_DEFAULT: usize = 1;
+    pub const JOBS_DEFAULT_STR: &str = "1";
+}
+
+/// Constants used for the Bundle command.
+pub mod bundle {
+    /// The default path of the exported bundle.
+    pub const OUTPUT_DEFAULT: &str = "bundle.zip";
 }
 
 /// Constants used for the Merge command.
 pub mod merge {
+    use crate::merge::{MergeWeighting, StdMode};
+
     /// Default section inside the CSV file to parse. STUDIS CSV files can have multiple sections ---
     /// e.g., section about the subject, section about the teacher, etc.
     pub const SECTION_DEFAULT: &str = super::create::SECTION_DEFAULT;
     /// The default output file of the merged CSV data.
     pub const OUTPUT_DEFAULT: &str = "merged.csv";
+
+    /// Default weighting of the merged files' means.
+    pub const WEIGHTING_DEFAULT: MergeWeighting = MergeWeighting::Equal;
+    pub const WEIGHTING_DEFAULT_STR: &str = "equal";
+
+    /// Default calculation of the merged standard deviation.
+    pub const STD_MODE_DEFAULT: StdMode = StdMode::Means;
+    pub const STD_MODE_DEFAULT_STR: &str = "means";
+
+    /// Default minimum similarity of question texts of different files for a fuzzy match.
+    pub const SIMILARITY_DEFAULT: f64 = super::create::SIMILARITY_DEFAULT;
+    pub const SIMILARITY_DEFAULT_STR: &str = super::create::SIMILARITY_DEFAULT_STR;
 }
@@ -1,162 +1,519 @@
-/// Module related to the ``create`` command.
+//! Module related to the ``create`` command.
 
-use rand::distributions::Uniform;
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-use anyhow::{Context, Result, anyhow};
-use serde_json as sj;
 use clap::ValueEnum;
 
 use std::path::{Path, PathBuf};
-use std::io::{Read, Write};
-use std::fs::File;
-use std::time;
-use std::env;
+use std::fs::{self as stdfs, File};
+use std::io::Write;
 
-use crate::with_parent_path;
-use crate::compiler;
-use crate::preproc;
-use crate::fs;
+use crate::error::{Error, Result};
+use crate::responses::{Bucket, ResponseSet, SectionResponses};
+use crate::diversity::UsageState;
+use crate::markup::{self, Markup};
+use crate::docx::DocxTemplate;
+use crate::placeholders::{self, Escape, Variables};
+use crate::matching::{self, QuestionMatch};
+use crate::compiler::{self, CompileJob, CompileOptions, Compiler};
+use crate::preproc::{Section, SurveyExport, SurveyOptions, COUNT_COLUMN, STD_COLUMN};
+use crate::config;
 
 
-#[derive(ValueEnum, Clone)]
+const C_OUTPUT_LATEX_REPLACE_KEY: &str = "{AUTO_GEN}";
+const C_OUTPUT_LATEX_REPLACE_PREFIX: &str = "{AUTO_GEN";
+
+
+/// The format of the generated report.
+#[derive(ValueEnum, Clone, Debug, PartialEq, Eq)]
 pub enum OutputFormat {
+    /// LaTeX source.
     #[clap(alias = "tex")]
     Latex,
-    Pdf
+    /// PDF, compiled from the LaTeX source.
+    Pdf,
+    /// Markdown. The template is a Markdown file and the LaTeX markup of responses is converted.
+    #[clap(alias = "md")]
+    Markdown,
+    /// HTML. The template is an HTML file and the LaTeX markup of responses is converted.
+    Html,
+    /// Plain text. The template is a text file and the LaTeX markup of responses is removed.
+    #[clap(alias = "text")]
+    Txt,
+    /// Word document. The template is a DOCX file and bold/italic markup of responses is converted.
+    #[clap(alias = "word")]
+    Docx
 }
 
+impl OutputFormat {
+    /// Extension of the output file.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Latex => "tex",
+            Self::Pdf => "pdf",
+            Self::Markdown => "md",
+            Self::Html => "html",
+            Self::Txt => "txt",
+            Self::Docx => "docx"
+        }
+    }
 
-/// Function that processes the CLI command ``create``
-/// It returns a string representing the output file's path.
-pub fn command_create(
-    studis_csv_filepath: &PathBuf,
-    response_json_filepath: &PathBuf,
-    tex_template_filepath: &PathBuf,
-    section: &str,
-    format: &OutputFormat,
-    output_filepath: &Option<PathBuf>
-) -> Result<String> {
-    const E_NOT_MAPPING: &str = "Not a JSON mapping";
-
-    const C_MEAN_CSV_KEY: &str = "Povprečje";
-    const C_STD_CSV_KEY: &str = "Standardni odklon";
-    const C_JSON_MAP_QUESTION_KEY: &str = "Vprašanje";
-
-    const C_OUTPUT_LATEX_REPLACE_KEY: &str = "{AUTO_GEN}";
-    const C_OUTPUT_LATEX_MEAN_KEY: &str = "{MEAN}";
-    const C_OUTPUT_LATEX_STD_KEY: &str = "{STD}";
-
-    // Check the output file
-    let mut file: File;
-    let mut output_fdata: String = String::new();
-    let mut output_parts = Vec::new();
-    file = File::open(tex_template_filepath).with_context(|| format!("could not open tex file ({tex_template_filepath:?})"))?;
-    file.read_to_string(&mut output_fdata).with_context(|| format!("Unable to read file {tex_template_filepath:?}"))?;
-
-    if !output_fdata.contains(C_OUTPUT_LATEX_REPLACE_KEY) {
-        return Err(anyhow!(
+    /// The markup language of the generated content. ``None`` for LaTeX-based formats
+    /// and DOCX, which is rendered by [`DocxTemplate`].
+    pub fn markup(&self) -> Option<Markup> {
+        match self {
+            Self::Latex | Self::Pdf | Self::Docx => None,
+            Self::Markdown => Some(Markup::Markdown),
+            Self::Html => Some(Markup::Html),
+            Self::Txt => Some(Markup::Plain)
+        }
+    }
+
+    /// The escaping of the values substituted into a template of this format.
+    /// DOCX text is escaped when the document is written.
+    pub fn escape(&self) -> Escape {
+        match (self, self.markup()) {
+            (Self::Docx, _) => Escape::None,
+            (_, Some(markup)) => Escape::Markup(markup),
+            (_, None) => Escape::Latex
+        }
+    }
+}
+
+
+/// Options of the ``create`` command.
+#[derive(Clone, Debug)]
+pub struct CreateOptions {
+    /// Name of the default grades section (e.g., "Anketa o izvajalcu"). It fills the ``{AUTO_GEN}``
+    /// placeholder, using the top-level questions of the responses file.
+    pub section: String,
+    /// The format of the output file.
+    pub format: OutputFormat,
+    /// Path of the output file. When ``None``, the path is derived from the template's path.
+    pub output_filepath: Option<PathBuf>,
+    /// Seed of the response selection. The same seed and inputs always produce the same document.
+    /// When ``None``, a random seed is used.
+    pub seed: Option<u64>,
+    /// User variables (e.g., ``CANDIDATE``), replaced in the responses and the template.
+    pub variables: Variables,
+    /// Minimum similarity (between 0 and 1) of a question's text in the responses file and the STUDIS export
+    /// for a fuzzy match (see [`matching`]).
+    pub similarity: f64,
+    /// State file of the used responses (see [`crate::diversity`]). When given, the least used responses are
+    /// preferred, so that reports of one session differ.
+    pub usage_filepath: Option<PathBuf>,
+    /// Options of reading the STUDIS export (e.g., its encoding).
+    pub survey: SurveyOptions,
+    /// Options of the LaTeX compilation (PDF format only).
+    pub compile: CompileOptions
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        Self {
+            section: config::create::SECTION_DEFAULT.to_string(),
+            format: config::create::FORMAT_DEFAULT,
+            output_filepath: None,
+            seed: None,
+            variables: Variables::new(),
+            similarity: config::create::SIMILARITY_DEFAULT,
+            usage_filepath: None,
+            survey: SurveyOptions::default(),
+            compile: CompileOptions::default()
+        }
+    }
+}
+
+
+/// A location of generated content in the template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placeholder {
+    /// The placeholder, as written in the template (e.g., ``{AUTO_GEN:Anketa o predmetu}``).
+    pub marker: String,
+    /// The STUDIS section whose responses fill the placeholder. ``None`` means the default section.
+    pub section: Option<String>
+}
+
+
+/// Returns the distinct placeholders of the ``template``, in order of appearance.
+/// ``{AUTO_GEN}`` marks the responses of the default section and ``{AUTO_GEN:<section>}``
+/// the responses of the given section.
+pub fn template_placeholders(template: &str) -> Vec<Placeholder> {
+    let mut placeholders: Vec<Placeholder> = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(C_OUTPUT_LATEX_REPLACE_PREFIX) {
+        rest = &rest[start + C_OUTPUT_LATEX_REPLACE_PREFIX.len()..];
+        let placeholder = if rest.starts_with('}') {
+            Some(Placeholder { marker: C_OUTPUT_LATEX_REPLACE_KEY.to_string(), section: None })
+        }
+        else if let Some(tail) = rest.strip_prefix(':')
+            && let Some(end) = tail.find(['}', '\n'])
+            && tail[end..].starts_with('}')
+        {
+            Some(Placeholder {
+                marker: format!("{C_OUTPUT_LATEX_REPLACE_PREFIX}:{}}}", &tail[..end]),
+                section: Some(tail[..end].trim().to_string())
+            })
+        }
+        else {
+            None
+        };
+
+        if let Some(placeholder) = placeholder && !placeholders.contains(&placeholder) {
+            placeholders.push(placeholder);
+        }
+    }
+
+    placeholders
+}
+
+
+/// Reads the LaTeX template at ``tex_template_filepath`` and checks that it marks
+/// the location of the generated content.
+pub fn load_template(tex_template_filepath: &Path) -> Result<String> {
+    let template = stdfs::read_to_string(tex_template_filepath)
+        .map_err(|e| Error::io("could not read tex file", tex_template_filepath, e))?;
+    check_template(&template, tex_template_filepath)?;
+    Ok(template)
+}
+
+
+/// Checks that the ``template`` (read from ``tex_template_filepath``) marks the location of the generated content.
+fn check_template(template: &str, tex_template_filepath: &Path) -> Result<()> {
+    if template_placeholders(template).is_empty() {
+        return Err(Error::Template(format!(
             "output file ({tex_template_filepath:?}) does not mark the location \
             of automatically-generated content (generated by this script). Mark it by writing \
-            \"{C_OUTPUT_LATEX_REPLACE_KEY}\" somewhere in the file"
-        ));
+            \"{C_OUTPUT_LATEX_REPLACE_KEY}\" (or \"{C_OUTPUT_LATEX_REPLACE_PREFIX}:<section>}}\") somewhere in the file"
+        )));
     }
 
-    // Process STUDIS CSV file.
-    let fdata = fs::read_file_universal(studis_csv_filepath).with_context(|| "unable to read STUDIS CSV")?;
-    let csvgrades = preproc::extract_section_columns(preproc::preprocess_candidate_csv(fdata), section)?;
+    Ok(())
+}
 
-    // Process JSON file. This is the file containing responses for each category and each grade.
-    file = File::open(response_json_filepath).with_context(|| format!("could not open responses file ({response_json_filepath:?})"))?;
-    let json_map: sj::Map<_, _> = sj::from_reader(file).with_context(|| E_NOT_MAPPING)?;
-    let categories: &sj::Map<_, _> = json_map[C_JSON_MAP_QUESTION_KEY].as_object().with_context(|| E_NOT_MAPPING)?;
-    let mut idx: usize;
-    let mut start_size: usize;
 
-    let mut mean: f64;
-    // let mut std: f64;
-    let mut smean: &str;
-    let mut sstd: &str;
+/// The result of the ``create`` command.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// Path of the output file.
+    pub path: PathBuf,
+    /// Seed that was used for the response selection. Pass it to [`CreateOptions::seed`] to reproduce the document.
+    pub seed: u64,
+    /// The STUDIS questions matched to the questions of the responses file, for each placeholder's section.
+    pub matches: Vec<(String, QuestionMatch)>
+}
 
-    let mut rgn = thread_rng();
 
-    // Iterate each category/question of the JSON responses file
-    for (cat, grades_json) in categories {
-        // Get index of the question matching JSON category
-        idx = csvgrades.get(C_JSON_MAP_QUESTION_KEY).with_context(|| "CSV is missing questions key.")?
-            .iter().position(|x| x == cat).with_context(|| format!("CSV is missing category \"{cat}\""))?;
-
-        // Read the String of the mean and std, then parse them to float
-        smean = &csvgrades.get(C_MEAN_CSV_KEY).with_context(|| "CSV is missing the mean grade value key")?[idx];
-        mean = smean.parse().with_context(|| format!("failed to parse {smean} as a float"))?;
-        sstd = &csvgrades.get(C_STD_CSV_KEY).with_context(|| "CSV is missing the std of grade key")?[idx];
-
-        // Obtain the mapping of min. grade => array of String responses
-        let grades_json = grades_json.as_object().cloned().with_context(|| E_NOT_MAPPING)?;
-        let mut grades = Vec::with_capacity(grades_json.len());
-        // Try to parse each grade into a float. If any fails, return the error.
-        for k in grades_json.keys() {
-            let parsed: f64 = k.parse().with_context(|| format!("grades must be floats (\"{k}\")"))?;
-            grades.push((k, parsed));
+/// A generated report, which is not yet compiled (PDF only) and written to its file (see [`Document::finish`]).
+#[derive(Debug)]
+pub struct Document {
+    /// The report. Its file is created by [`Document::write`].
+    pub report: Report,
+    /// The LaTeX source of a PDF report, which must be compiled. ``None`` for other formats.
+    pub latex: Option<CompileJob>,
+    /// Content of the output file (other formats).
+    data: Vec<u8>,
+    /// The template, with its variables substituted.
+    template: String,
+    /// Descriptions and texts of the selected responses (see [`locate_errors`]).
+    origins: Vec<(String, String)>,
+    /// State file and the responses used by this report, which are added to it once the report is written.
+    pub(crate) usage: Option<(PathBuf, UsageState)>
+}
+
+
+impl Document {
+    /// Compiles the report (PDF only) with the ``compiler`` and writes it to its file.
+    pub fn finish(self, compiler: &mut Compiler) -> Result<Report> {
+        let compiled = match &self.latex {
+            Some(job) => Some(compiler.compile(&job.latex, &job.root).map_err(|e| self.locate_errors(e))?),
+            None => None
+        };
+        self.write(compiled)
+    }
+
+    /// Writes the report to its file, which contains the ``compiled`` PDF of a PDF report,
+    /// and records the used responses (see [`CreateOptions::usage_filepath`]).
+    pub fn write(mut self, compiled: Option<Vec<u8>>) -> Result<Report> {
+        let data = compiled.unwrap_or(self.data);
+        let output = &self.report.path;
+        let mut file = File::create(output).map_err(|e| Error::io("could not create output file", output, e))?;
+        file.write_all(&data).map_err(|e| Error::io("could not write output file", output, e))?;
+        self.data = Vec::new();
+
+        self.save_usage()?;
+        Ok(self.report)
+    }
+
+    /// Adds the used responses to the state file (see [`CreateOptions::usage_filepath`]), unless already added.
+    fn save_usage(&mut self) -> Result<()> {
+        match self.usage.take() {
+            Some((filepath, picks)) => picks.add_to(&filepath),
+            None => Ok(())
         }
+    }
 
-        // Sort the grades by the parsed value
-        grades.sort_by(|(_, a), (_, b)| b.total_cmp(a));
-
-        // Iterate sorted keys from largest grade to lowest, compare each parsed grade to the mean value of CSV grades
-        // and stop when we find the key that is lower or equal than the mean.
-        start_size = output_parts.len();
-
-        let epoch_ns;
-        let responses;
-        let response_idx;
-        let response;
-        let response_str;
-        for (sgrade, grade) in grades.iter() {
-            if (grade * 10000.0) as usize <= (mean * 10000.0) as usize {  // Prevent influence of numeric error
-                let v = &grades_json[*sgrade];  // no need to check existence,because grades is generated from grades_json
-
-                // Query elapsed nanoseconds in order to improve randomness.
-                epoch_ns = time::SystemTime::now()
-                    .duration_since(time::UNIX_EPOCH)?
-                    .as_nanos();
-
-                // Try to parse the array of possible responses under the given grade.
-                responses = v.as_array().with_context(|| format!(
-                    "value of Category->Grade->Value must be an array of strings. Found {v:?}"
-                ))?;
-
-                if responses.is_empty() {
-                    return Err(anyhow!("there are no defined responses for grade {sgrade}, category {cat:?}"));
-                }
-
-                response_idx = (
-                    (epoch_ns % responses.len() as u128
-                    + rgn.sample(Uniform::new(0, responses.len())) as u128) % responses.len() as u128
-                ) as usize;
-                response = &responses[response_idx];
-                response_str = response.as_str().with_context(
-                    || format!("responses must be strings ({response} is not)")
-                )?;
-                output_parts.push(
-                    response_str.replace(C_OUTPUT_LATEX_MEAN_KEY, smean)
-                                .replace(C_OUTPUT_LATEX_STD_KEY, sstd)
-                );
-                break;
-            }
+    /// Sets the origin of the lines of the compilation ``error`` (see [`compiler::TexError`])
+    /// to the responses or the template.
+    pub fn locate_errors(&self, error: Error) -> Error {
+        match &self.latex {
+            Some(job) => locate_errors(error, &job.latex, &self.template, &self.origins),
+            None => error
+        }
+    }
+}
+
+
+/// Creates the random number generator used for response selection.
+/// The generator is fixed (instead of [`rand::rngs::StdRng`]), so that seeds remain reproducible across versions.
+pub fn seeded_rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+
+/// Picks one of the responses of a bucket.
+#[derive(Clone, Debug)]
+pub struct Picker {
+    rng: ChaCha8Rng,
+    usage: Option<UsageState>,
+    /// Responses picked by this picker.
+    picks: UsageState
+}
+
+impl Picker {
+    /// Creates a picker with the generator of the ``seed`` (see [`seeded_rng`]). When the ``usage`` of responses
+    /// is given, the picker prefers the least used responses of a bucket and records its picks.
+    pub fn new(seed: u64, usage: Option<UsageState>) -> Self {
+        Self { rng: seeded_rng(seed), usage, picks: UsageState::default() }
+    }
+
+    /// Picks a random response of the ``bucket``.
+    pub fn pick<'a>(&mut self, bucket: &'a Bucket) -> &'a str {
+        let choices = &bucket.responses;
+        let Some(usage) = &mut self.usage else {
+            return &choices[self.rng.gen_range(0..choices.len())];
+        };
+
+        let counts: Vec<u64> = choices.iter().map(|r| usage.count(bucket, r)).collect();
+        let least = counts.iter().copied().min().unwrap_or_default();
+        let candidates: Vec<&String> = choices.iter().zip(&counts).filter(|(_, c)| **c == least).map(|(r, _)| r).collect();
+        let response = candidates[self.rng.gen_range(0..candidates.len())];
+        usage.record(bucket, response);
+        self.picks.record(bucket, response);
+        response
+    }
+
+    /// Returns the responses picked by this picker, when the usage of responses was given.
+    pub fn into_picks(self) -> Option<UsageState> {
+        self.usage.map(|_| self.picks)
+    }
+}
+
+
+/// Matches the questions of the section's ``responses`` to the questions of the STUDIS ``section``
+/// (see [`matching::match_questions`]).
+pub fn match_section(section: &Section, responses: &SectionResponses, similarity: f64) -> Result<Vec<QuestionMatch>> {
+    matching::match_questions(&section.questions(), &responses.questions, similarity)
+}
+
+
+/// Selects a random response for each question of the section's ``responses``, based on the
+/// grades of the STUDIS ``section`` (see [`preproc::SurveyExport`]).
+/// The questions are located in the ``section`` by their ``matches`` (see [`match_section`]).
+/// The returned responses have their placeholders (see [`placeholders`]) and user ``variables`` replaced
+/// and are ordered as the questions in ``responses``.
+pub fn select_responses(
+    section: &Section,
+    responses: &SectionResponses,
+    matches: &[QuestionMatch],
+    variables: &Variables,
+    picker: &mut Picker
+) -> Result<Vec<String>> {
+    fn missing(column: &str) -> Error {
+        Error::Survey(format!("CSV is missing the \"{column}\" column"))
+    }
+
+    let mut output_parts = Vec::with_capacity(responses.questions.len());
+
+    // Iterate each category/question of the JSON responses file
+    for (question, matched) in responses.questions.iter().zip(matches) {
+        let cat = &question.text;
+        // The CSV question matching JSON category. The standard deviation and the number
+        // of respondents are optional and are only used by rules.
+        let row = &section.rows[matched.index];
+
+        // Find the first bucket whose rule matches the grades (e.g., the threshold with the largest
+        // minimum grade that is lower or equal than the mean).
+        let bucket = question.bucket_for(row)
+            .ok_or_else(|| Error::Responses(format!(
+                "no response matches the grades (mean {}, std {}, respondents {}) of category \"{cat}\"",
+                row.mean,
+                row.std.map(|s| s.to_string()).unwrap_or("unknown".to_string()),
+                row.count.map(|c| c.to_string()).unwrap_or("unknown".to_string())
+            )))?;
+        let response = picker.pick(bucket);
+        // Responses are LaTeX in every output format (they are converted when rendered)
+        output_parts.push(placeholders::substitute_escaped(response, Escape::Latex, |name, arg| match name {
+            placeholders::MEAN => placeholders::format_number(name, row.mean, row.decimals, arg).map(Some),
+            placeholders::STD => {
+                let std = row.std.ok_or_else(|| missing(STD_COLUMN))?;
+                placeholders::format_number(name, std, row.decimals, arg).map(Some)
+            },
+            placeholders::COUNT => Ok(Some(row.count.ok_or_else(|| missing(COUNT_COLUMN))?.to_string())),
+            placeholders::QUESTION => Ok(Some(cat.clone())),
+            placeholders::SECTION => Ok(Some(section.title.clone())),
+            _ => placeholders::variable(variables, name, arg)
+        })?);
+    }
+
+    Ok(output_parts)
+}
+
+
+/// Replaces the ``{SECTION}`` placeholder (with the default ``section``) and the user ``variables``
+/// in the ``template``, escaping the values according to ``escape`` (see [`OutputFormat::escape`]).
+/// The ``{AUTO_GEN}`` placeholders are left in place.
+pub fn substitute_template(template: &str, section: &str, variables: &Variables, escape: Escape) -> Result<String> {
+    placeholders::substitute_escaped(template, escape, |name, arg| match name {
+        placeholders::SECTION => Ok(Some(section.to_string())),
+        _ => placeholders::variable(variables, name, arg)
+    })
+}
+
+
+/// Inserts the generated parts into the ``template``, at the location of their placeholder (see [`template_placeholders`]).
+/// The LaTeX parts are converted to the markup of the ``format`` (see [`OutputFormat::markup`]).
+/// The ``seed`` of the response selection is recorded (at the first placeholder) as a comment and, for LaTeX,
+/// as the ``CloggenSeed`` entry of the PDF document information. Plain text has no comments, so the seed is only
+/// returned (see [`Report::seed`]).
+/// DOCX templates are rendered by [`DocxTemplate::render`], which records the seed as a custom property.
+pub fn render_template(
+    template: &str,
+    generated: &[(Placeholder, Vec<String>)],
+    seed: u64,
+    format: &OutputFormat
+) -> String {
+    let mut output = template.to_string();
+    for (i, (placeholder, parts)) in generated.iter().enumerate() {
+        let mut content = match format.markup() {
+            None => parts.join("\n"),
+            Some(Markup::Plain) => parts.iter().map(|p| markup::convert_latex(p, Markup::Plain)).collect::<Vec<_>>().join(" "),
+            Some(markup) => parts.iter()
+                .map(|p| markup::convert_latex(p, markup))
+                .collect::<Vec<_>>()
+                .join(markup::paragraph_break(markup))
+        };
+        if i == 0 {
+            content = match format.markup() {
+                None => format!("% cloggen seed: {seed}\n\\special{{pdf:docinfo << /CloggenSeed ({seed}) >>}}\n{content}"),
+                Some(Markup::Plain) => content,
+                Some(_) => format!("<!-- cloggen seed: {seed} -->\n{content}")
+            };
         }
+        output = output.replace(&placeholder.marker, &content);
+    }
+    output
+}
 
-        // Check if loop was not break-ed;
-        if start_size == output_parts.len() {
-            return Err(anyhow!("could not find grade below mean ({mean}) for category \"{cat}\""));
+
+/// Sets the origin of the lines of the compilation ``error`` (see [`compiler::TexError`]).
+/// A line of the ``latex`` source either belongs to a response, whose description and text are given
+/// by the ``origins``, or to the ``template``.
+fn locate_errors(mut error: Error, latex: &str, template: &str, origins: &[(String, String)]) -> Error {
+    if let Error::Compile { errors, .. } = &mut error {
+        let lines: Vec<&str> = latex.lines().collect();
+        for tex_error in errors {
+            let Some(line) = tex_error.line.and_then(|l| lines.get(l.checked_sub(1)?)) else {
+                continue;
+            };
+            tex_error.origin = origins.iter()
+                .find(|(_, response)| response.lines().any(|l| l == *line))
+                .map(|(origin, response)| format!("{origin}: \"{response}\""))
+                .or_else(|| template.lines().position(|l| l == *line).map(|i| format!("the template (line {})", i + 1)));
         }
     }
+    error
+}
+
+
+/// Function that processes the CLI command ``create``
+/// It returns the path of the output file and the used seed.
+pub fn command_create(
+    studis_csv_filepath: &Path,
+    response_json_filepath: &Path,
+    tex_template_filepath: &Path,
+    options: &CreateOptions
+) -> Result<Report> {
+    // Process STUDIS CSV file.
+    let survey = SurveyExport::from_file(studis_csv_filepath, &options.survey)?;
+    create_from_survey(&survey, response_json_filepath, tex_template_filepath, options)
+}
+
+
+/// Same as [`command_create`], but with the STUDIS ``survey`` already read (see [`SurveyExport::from_file`]).
+pub fn create_from_survey(
+    survey: &SurveyExport,
+    response_json_filepath: &Path,
+    tex_template_filepath: &Path,
+    options: &CreateOptions
+) -> Result<Report> {
+    generate_document(survey, response_json_filepath, tex_template_filepath, options)?
+        .finish(&mut Compiler::new(options.compile.clone()))
+}
+
+
+/// Generates the report from the STUDIS ``survey``, without compiling and writing it (see [`Document::finish`]).
+pub fn generate_document(
+    survey: &SurveyExport,
+    response_json_filepath: &Path,
+    tex_template_filepath: &Path,
+    options: &CreateOptions
+) -> Result<Document> {
+    // A DOCX template is edited in place, other templates are text.
+    let mut docx = match options.format {
+        OutputFormat::Docx => Some(DocxTemplate::open(tex_template_filepath)?),
+        _ => None
+    };
+    let template = match &mut docx {
+        Some(docx) => {
+            docx.substitute(|text| substitute_template(text, &options.section, &options.variables, Escape::None))?;
+            let text = docx.text();
+            check_template(&text, tex_template_filepath)?;
+            text
+        },
+        None => substitute_template(
+            &load_template(tex_template_filepath)?, &options.section, &options.variables, options.format.escape()
+        )?
+    };
 
-    // Insert the generated LaTeX into our TeX source file
-    output_fdata = output_fdata.replace(C_OUTPUT_LATEX_REPLACE_KEY, &(output_parts.join("\n")));
+    // Process JSON file. This is the file containing responses for each category and each grade.
+    let responses = ResponseSet::from_file(response_json_filepath)?;
+    let seed = options.seed.unwrap_or_else(|| thread_rng().r#gen());
+    let usage = options.usage_filepath.as_deref().map(UsageState::load).transpose()?;
+    let mut picker = Picker::new(seed, usage);
+
+    // Generate the responses of each placeholder's section
+    let mut generated = Vec::new();
+    let mut matches = Vec::new();
+    let mut origins = Vec::new();
+    for placeholder in template_placeholders(&template) {
+        let section = placeholder.section.as_deref().unwrap_or(&options.section);
+        let section_responses = responses.section(placeholder.section.as_deref(), &options.section)
+            .ok_or_else(|| Error::Responses(format!("responses file has no responses for section \"{section}\"")))?;
+        let survey_section = survey.section(section)?;
+        let section_matches = match_section(survey_section, section_responses, options.similarity)?;
+        let parts = select_responses(survey_section, section_responses, &section_matches, &options.variables, &mut picker)?;
+        matches.extend(section_matches.into_iter().map(|m| (section.to_string(), m)));
+        origins.extend(section_responses.questions.iter().zip(&parts).map(|(question, part)| (
+            format!("the response to \"{}\" [{section}]", question.text), part.clone()
+        )));
+        generated.push((placeholder, parts));
+    }
 
     // If no output path is given, assume the source file without extension as a basename, otherwise use the given path.
-    let mut output = match output_filepath {
+    let mut output = match &options.output_filepath {
         Some(path) => path.display().to_string(),
         None => {
             // We can unwrap here because we tested the path by opening the file above.
@@ -174,25 +531,89 @@ pub fn command_create(
         }
     };
 
-    match format {
-        OutputFormat::Latex => {
-            if !output.ends_with(".tex") {
-                output += ".tex";
-            }
+    let extension = format!(".{}", options.format.extension());
+    if !output.ends_with(&extension) {
+        output += &extension;
+    }
 
-            file = File::create(&output).with_context(|| "could not write output LaTex")?;
-            file.write_all(output_fdata.as_bytes())?;
-        },
-        OutputFormat::Pdf => {
-            if !output.ends_with(".pdf") {
-                output += ".pdf";
+    // Insert the generated LaTeX into our source file
+    let (data, latex) = match docx {
+        Some(docx) => (docx.render(&generated, seed)?, None),
+        None => {
+            let output_fdata = render_template(&template, &generated, seed, &options.format);
+            match options.format {
+                OutputFormat::Pdf => (Vec::new(), Some(CompileJob {
+                    latex: output_fdata,
+                    root: compiler::source_root(tex_template_filepath).to_path_buf()
+                })),
+                _ => (output_fdata.into_bytes(), None)
             }
+        }
+    };
+
+    // Remember the used responses only once the report is successfully created.
+    let usage = options.usage_filepath.clone().zip(picker.into_picks());
+    Ok(Document { report: Report { path: PathBuf::from(output), seed, matches }, latex, data, template, origins, usage })
+}
+
 
-            let pdfdata = with_parent_path!(tex_template_filepath, {compiler::compile_latex(output_fdata)?});
-            file = File::create(&output).with_context(|| "could not create final PDF")?;
-            file.write_all(&pdfdata)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto_gen(parts: &[&str]) -> Vec<(Placeholder, Vec<String>)> {
+        let placeholder = Placeholder { marker: C_OUTPUT_LATEX_REPLACE_KEY.to_string(), section: None };
+        vec![(placeholder, parts.iter().map(|p| p.to_string()).collect())]
+    }
+
+    fn bucket(responses: &[&str]) -> Bucket {
+        Bucket {
+            key: "4".to_string(),
+            path: "$[\"Vprašanje\"][\"4\"]".to_string(),
+            rule: Default::default(),
+            responses: responses.iter().map(|r| r.to_string()).collect()
         }
     }
 
-    Ok(output)
+    #[test]
+    fn picks_reproducibly() {
+        let bucket = bucket(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let picks = |seed| {
+            let mut picker = Picker::new(seed, None);
+            (0..20).map(|_| picker.pick(&bucket).to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(1234), picks(1234));
+        assert_ne!(picks(1234), picks(4321));
+    }
+
+    #[test]
+    fn picks_least_used_responses() {
+        let bucket = bucket(&["a", "b", "c"]);
+        let mut usage = UsageState::default();
+        for response in ["a", "a", "b", "c", "c"] {
+            usage.record(&bucket, response);
+        }
+
+        let mut picker = Picker::new(1234, Some(usage));
+        assert_eq!(picker.pick(&bucket), "b");
+        let usage = picker.usage.as_ref().unwrap();
+        assert_eq!(["a", "b", "c"].map(|r| usage.count(&bucket, r)), [2, 2, 2]);
+
+        let picks = picker.into_picks().unwrap();
+        assert_eq!(["a", "b", "c"].map(|r| picks.count(&bucket, r)), [0, 1, 0]);
+    }
+
+    #[test]
+    fn renders_responses_as_paragraphs() {
+        let generated = auto_gen(&["Prvi \\textbf{odziv}.", "Drugi odziv."]);
+        assert_eq!(
+            render_template("# Mnenje\n\n{AUTO_GEN}\n", &generated, 1, &OutputFormat::Markdown),
+            "# Mnenje\n\n<!-- cloggen seed: 1 -->\nPrvi **odziv**.\n\nDrugi odziv.\n"
+        );
+        assert_eq!(
+            render_template("<p>{AUTO_GEN}</p>", &generated, 1, &OutputFormat::Html),
+            "<p><!-- cloggen seed: 1 -->\nPrvi <strong>odziv</strong>.\n<br><br>\nDrugi odziv.</p>"
+        );
+        assert_eq!(render_template("{AUTO_GEN}", &generated, 1, &OutputFormat::Txt), "Prvi odziv. Drugi odziv.");
+    }
 }
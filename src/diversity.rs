@@ -0,0 +1,69 @@
+//! Tracking of the used responses, so that reports generated in one session differ as much as possible.
+//!
+//! The state is stored as a small JSON file, which maps each bucket (by its JSON path, see [`Bucket::path`])
+//! to the number of uses of its responses. Delete the file to start a new session.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::fs;
+use std::io;
+
+use crate::error::{Error, Result};
+use crate::responses::Bucket;
+
+
+/// Numbers of uses of the responses.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UsageState {
+    /// Maps the bucket's path to the numbers of uses of its responses.
+    used: BTreeMap<String, BTreeMap<String, u64>>
+}
+
+
+impl UsageState {
+    /// Reads the state from ``filepath``. A missing file means a new (empty) state.
+    pub fn load(filepath: &Path) -> Result<Self> {
+        match fs::read_to_string(filepath) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| Error::Input(format!(
+                "invalid response usage file ({}): {e}. Delete it to start a new session.", filepath.display()
+            ))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::io("could not read response usage file", filepath, e))
+        }
+    }
+
+    /// Writes the state to ``filepath``.
+    pub fn save(&self, filepath: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(filepath, content).map_err(|e| Error::io("could not write response usage file", filepath, e))
+    }
+
+    /// Returns how many times the ``response`` of the ``bucket`` was used.
+    pub fn count(&self, bucket: &Bucket, response: &str) -> u64 {
+        self.used.get(&bucket.path).and_then(|b| b.get(response)).copied().unwrap_or(0)
+    }
+
+    /// Records a use of the ``response`` of the ``bucket``.
+    pub fn record(&mut self, bucket: &Bucket, response: &str) {
+        *self.used.entry(bucket.path.clone()).or_default().entry(response.to_string()).or_default() += 1;
+    }
+
+    /// Adds the uses of the ``other`` state to this state.
+    pub fn merge(&mut self, other: &UsageState) {
+        for (bucket, responses) in &other.used {
+            let used = self.used.entry(bucket.clone()).or_default();
+            for (response, count) in responses {
+                *used.entry(response.clone()).or_default() += count;
+            }
+        }
+    }
+
+    /// Adds the uses of this state to the state in ``filepath``.
+    pub fn add_to(&self, filepath: &Path) -> Result<()> {
+        let mut state = Self::load(filepath)?;
+        state.merge(self);
+        state.save(filepath)
+    }
+}
@@ -0,0 +1,528 @@
+//! DOCX (Word) output.
+//!
+//! A DOCX template is a Word document whose body contains the ``{AUTO_GEN}`` placeholders (see
+//! [`create::template_placeholders`]). The paragraph of a placeholder is rewritten with the generated responses,
+//! keeping the paragraph's formatting and the formatting of its first run. Bold and italic LaTeX markup of the
+//! responses (see [`markup::latex_runs`]) is converted to Word's formatting. Other placeholders
+//! (e.g., ``{CANDIDATE}``) may be anywhere in the text of the body.
+//!
+//! Word often splits the text of a paragraph into several runs, so placeholders are searched for in the text of
+//! the whole paragraph. A paragraph whose placeholder is split between runs is rewritten as a single run.
+//!
+//! The seed of the response selection is recorded as the ``CloggenSeed`` custom property of the document
+//! (File > Info > Properties in Word).
+//!
+//! [`create::template_placeholders`]: crate::create::template_placeholders
+
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::result::{ZipError, ZipResult};
+use zip::write::SimpleFileOptions;
+
+use std::io::{Cursor, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::fs;
+
+use crate::create::Placeholder;
+use crate::error::{Error, Result};
+use crate::markup::{self, Run};
+
+
+/// Path of the document body inside the DOCX archive.
+const DOCUMENT_PATH: &str = "word/document.xml";
+/// Path of the custom document properties inside the DOCX archive.
+const CUSTOM_PROPERTIES_PATH: &str = "docProps/custom.xml";
+/// Path of the content types of the archive's parts.
+const CONTENT_TYPES_PATH: &str = "[Content_Types].xml";
+/// Path of the package relationships, which link the custom properties.
+const RELATIONSHIPS_PATH: &str = "_rels/.rels";
+/// Name of the custom property, which records the seed of the response selection.
+const SEED_PROPERTY: &str = "CloggenSeed";
+
+
+/// A DOCX template.
+#[derive(Clone, Debug)]
+pub struct DocxTemplate {
+    path: PathBuf,
+    /// The template's archive.
+    archive: Vec<u8>,
+    /// The (modified) document body, in WordprocessingML.
+    document: String
+}
+
+
+impl DocxTemplate {
+    /// Reads the DOCX template at ``path``.
+    pub fn open(path: &Path) -> Result<Self> {
+        let archive = fs::read(path).map_err(|e| Error::io("could not read docx file", path, e))?;
+        let invalid = |e: &dyn std::fmt::Display| Error::Template(format!("{} is not a DOCX file: {e}", path.display()));
+
+        let mut zip = ZipArchive::new(Cursor::new(&archive)).map_err(|e| invalid(&e))?;
+        let mut document = String::new();
+        zip.by_name(DOCUMENT_PATH)
+            .map_err(|e| invalid(&e))?
+            .read_to_string(&mut document)
+            .map_err(|e| invalid(&e))?;
+
+        Ok(Self { path: path.to_path_buf(), archive, document })
+    }
+
+    /// Returns the text of the document body, one paragraph per line.
+    pub fn text(&self) -> String {
+        elements(&self.document, "w:p").into_iter()
+            .map(|range| paragraph_text(&self.document[range]))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Replaces the text of each paragraph with the result of ``substitute`` (e.g., [`crate::create::substitute_template`]).
+    /// The formatting of the runs is kept, unless a replaced placeholder is split between runs.
+    pub fn substitute(&mut self, mut substitute: impl FnMut(&str) -> Result<String>) -> Result<()> {
+        for range in elements(&self.document, "w:p").into_iter().rev() {
+            let paragraph = &self.document[range.clone()];
+            let text = paragraph_text(paragraph);
+            let new_text = substitute(&text)?;
+            if new_text == text {
+                continue;
+            }
+
+            // Substitute each run separately when no placeholder is split between runs,
+            // otherwise move the whole text into the first run.
+            let texts = elements(paragraph, "w:t");
+            let per_run: Option<Vec<String>> = texts.iter()
+                .map(|t| substitute(&element_text(&paragraph[t.clone()])).ok())
+                .collect();
+            let new_texts = match per_run {
+                Some(per_run) if per_run.concat() == new_text => per_run,
+                _ => std::iter::once(new_text).chain(std::iter::repeat_n(String::new(), texts.len().saturating_sub(1))).collect()
+            };
+
+            let mut new_paragraph = paragraph.to_string();
+            for (t, new_text) in texts.into_iter().zip(new_texts).rev() {
+                new_paragraph.replace_range(t, &text_xml(&new_text));
+            }
+            self.document.replace_range(range, &new_paragraph);
+        }
+        Ok(())
+    }
+
+    /// Inserts the generated LaTeX parts into the template, at the location of their placeholder,
+    /// and returns the DOCX file. The ``seed`` of the response selection is recorded as a custom property.
+    pub fn render(&self, generated: &[(Placeholder, Vec<String>)], seed: u64) -> Result<Vec<u8>> {
+        let mut document = self.document.clone();
+        for range in elements(&self.document, "w:p").into_iter().rev() {
+            let paragraph = &self.document[range.clone()];
+            if let Some(new_paragraph) = render_paragraph(paragraph, generated) {
+                document.replace_range(range, &new_paragraph);
+            }
+        }
+
+        self.write(&document, seed).map_err(|e| Error::Template(format!(
+            "could not create DOCX file from {}: {e}", self.path.display()
+        )))
+    }
+
+    /// Returns the template's archive with the ``document`` body and the ``seed`` property.
+    fn write(&self, document: &str, seed: u64) -> ZipResult<Vec<u8>> {
+        let mut zip = ZipArchive::new(Cursor::new(&self.archive))?;
+        let mut parts = vec![
+            (DOCUMENT_PATH, document.to_string()),
+            (CUSTOM_PROPERTIES_PATH, set_custom_property(read_part(&mut zip, CUSTOM_PROPERTIES_PATH)?, SEED_PROPERTY, &seed.to_string()))
+        ];
+        let content_types = read_part(&mut zip, CONTENT_TYPES_PATH)?.ok_or(ZipError::FileNotFound)?;
+        if !content_types.contains(&format!("/{CUSTOM_PROPERTIES_PATH}")) {
+            parts.push((CONTENT_TYPES_PATH, append_child(&content_types, "Types", &format!(
+                "<Override PartName=\"/{CUSTOM_PROPERTIES_PATH}\" \
+                ContentType=\"application/vnd.openxmlformats-officedocument.custom-properties+xml\"/>"
+            ))));
+        }
+        let relationships = read_part(&mut zip, RELATIONSHIPS_PATH)?.unwrap_or_else(|| {
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+            <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"/>".to_string()
+        });
+        if !relationships.contains(&format!("\"{CUSTOM_PROPERTIES_PATH}\"")) {
+            parts.push((RELATIONSHIPS_PATH, append_child(&relationships, "Relationships", &format!(
+                "<Relationship Id=\"rIdCloggenCustom\" \
+                Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/custom-properties\" \
+                Target=\"{CUSTOM_PROPERTIES_PATH}\"/>"
+            ))));
+        }
+
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for i in 0..zip.len() {
+            let file = zip.by_index(i)?;
+            match parts.iter().position(|(path, _)| *path == file.name()) {
+                Some(j) => {
+                    let (path, content) = parts.remove(j);
+                    writer.start_file(path, options)?;
+                    writer.write_all(content.as_bytes())?;
+                },
+                None => writer.raw_copy_file(file)?
+            }
+        }
+        for (path, content) in parts {
+            writer.start_file(path, options)?;
+            writer.write_all(content.as_bytes())?;
+        }
+        Ok(writer.finish()?.into_inner())
+    }
+}
+
+
+/// Returns the content of the archive's part at ``path``, if the part exists.
+fn read_part(zip: &mut ZipArchive<Cursor<&Vec<u8>>>, path: &str) -> ZipResult<Option<String>> {
+    let mut content = String::new();
+    match zip.by_name(path) {
+        Ok(mut file) => file.read_to_string(&mut content)?,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    Ok(Some(content))
+}
+
+
+/// Sets the custom property ``name`` to the text ``value`` in the custom ``properties`` (a new part when ``None``).
+fn set_custom_property(properties: Option<String>, name: &str, value: &str) -> String {
+    let mut properties = properties.unwrap_or_else(|| {
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+        <Properties xmlns=\"http://schemas.openxmlformats.org/officeDocument/2006/custom-properties\" \
+        xmlns:vt=\"http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes\"/>".to_string()
+    });
+    let name_attribute = format!("name=\"{}\"", escape(name));
+    if let Some(range) = elements(&properties, "property").into_iter().find(|r| properties[r.clone()].contains(&name_attribute)) {
+        properties.replace_range(range, "");
+    }
+
+    // Property identifiers start at 2 and are unique
+    let pid = properties.match_indices("pid=\"")
+        .filter_map(|(i, pid)| properties[i + pid.len()..].split('"').next()?.parse::<u32>().ok())
+        .max()
+        .map_or(2, |pid| pid + 1);
+    append_child(&properties, "Properties", &format!(
+        "<property fmtid=\"{{D5CDD505-2E9C-101B-9397-08002B2CF9AE}}\" pid=\"{pid}\" {name_attribute}>\
+        <vt:lpwstr>{}</vt:lpwstr></property>", escape(value)
+    ))
+}
+
+
+/// Appends the ``child`` to the content of the (root) element ``name`` of the ``xml``.
+fn append_child(xml: &str, name: &str, child: &str) -> String {
+    let mut xml = xml.to_string();
+    if let Some(i) = xml.rfind(&format!("</{name}>")) {
+        xml.insert_str(i, child);
+    }
+    else if let Some(range) = elements(&xml, name).pop() {
+        // Empty element, e.g., <Types/>
+        let element = xml[range.clone()].trim_end_matches('>').trim_end_matches('/').to_string();
+        xml.replace_range(range, &format!("{element}>{child}</{name}>"));
+    }
+    xml
+}
+
+
+/// Rewrites the ``paragraph`` with the generated parts in place of its placeholders.
+/// Returns ``None`` when the paragraph contains no placeholder.
+fn render_paragraph(paragraph: &str, generated: &[(Placeholder, Vec<String>)]) -> Option<String> {
+    let mut text = paragraph_text(paragraph);
+    if !generated.iter().any(|(placeholder, _)| text.contains(&placeholder.marker)) {
+        return None;
+    }
+
+    // Split the text at the placeholders
+    let mut runs = Vec::new();
+    while let Some((start, placeholder, parts)) = generated.iter()
+        .filter_map(|(placeholder, parts)| text.find(&placeholder.marker).map(|start| (start, placeholder, parts)))
+        .min_by_key(|(start, ..)| *start)
+    {
+        runs.push(Run { text: text[..start].to_string(), ..Default::default() });
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                runs.push(Run { text: " ".to_string(), ..Default::default() });
+            }
+            runs.extend(markup::latex_runs(part));
+        }
+        text = text[start + placeholder.marker.len()..].to_string();
+    }
+    runs.push(Run { text, ..Default::default() });
+
+    // Keep the formatting of the paragraph and its first run
+    let open_end = paragraph.find('>').map_or(0, |i| i + 1);
+    let properties = elements(paragraph, "w:pPr").first().map_or("", |r| &paragraph[r.clone()]);
+    let run_properties = elements(paragraph, "w:r").first()
+        .and_then(|r| {
+            let run = &paragraph[r.clone()];
+            elements(run, "w:rPr").first().map(|p| element_content(&run[p.clone()]).to_string())
+        })
+        .unwrap_or_default();
+
+    let runs: String = runs.iter()
+        .filter(|run| !run.text.is_empty())
+        .map(|run| run_xml(run, &run_properties))
+        .collect();
+    Some(format!("{}{properties}{runs}</w:p>", &paragraph[..open_end]))
+}
+
+
+/// Returns the WordprocessingML of the ``run``, formatted with the ``base`` run properties.
+fn run_xml(run: &Run, base: &str) -> String {
+    let mut properties = base.to_string();
+    if run.bold && elements(&properties, "w:b").is_empty() {
+        insert_property(&mut properties, "<w:b/>", &["w:rStyle", "w:rFonts"]);
+    }
+    if run.italic && elements(&properties, "w:i").is_empty() {
+        insert_property(&mut properties, "<w:i/>", &["w:rStyle", "w:rFonts", "w:b", "w:bCs"]);
+    }
+
+    let properties = if properties.is_empty() { properties } else { format!("<w:rPr>{properties}</w:rPr>") };
+    let content = run.text.split('\n').map(text_xml).collect::<Vec<_>>().join("<w:br/>");
+    format!("<w:r>{properties}{content}</w:r>")
+}
+
+
+/// Inserts the ``property`` into the run ``properties``, after the elements that must precede it.
+fn insert_property(properties: &mut String, property: &str, preceding: &[&str]) {
+    let position = preceding.iter()
+        .filter_map(|name| elements(properties, name).last().map(|r| r.end))
+        .max()
+        .unwrap_or(0);
+    properties.insert_str(position, property);
+}
+
+
+/// Returns the ``<w:t>`` element containing the ``text``.
+fn text_xml(text: &str) -> String {
+    format!("<w:t xml:space=\"preserve\">{}</w:t>", escape(text))
+}
+
+
+/// Returns the text of the ``paragraph``.
+fn paragraph_text(paragraph: &str) -> String {
+    elements(paragraph, "w:t").into_iter().map(|t| element_text(&paragraph[t])).collect()
+}
+
+
+/// Returns the (unescaped) text of the ``element``.
+fn element_text(element: &str) -> String {
+    unescape(element_content(element))
+}
+
+
+/// Returns the content of the ``element``, without its start and end tags.
+fn element_content(element: &str) -> &str {
+    if element.ends_with("/>") && element.find('>') == Some(element.len() - 1) {
+        return "";
+    }
+    let start = element.find('>').map_or(element.len(), |i| i + 1);
+    let end = element.rfind("</").unwrap_or(element.len()).max(start);
+    &element[start..end]
+}
+
+
+/// Returns the byte ranges of the outermost elements named ``name`` in the ``xml``.
+fn elements(xml: &str, name: &str) -> Vec<Range<usize>> {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut ranges = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut position = 0;
+
+    while let Some(i) = xml[position..].find('<').map(|i| i + position) {
+        let tag = &xml[i..];
+        let tag_end = tag.find('>').map_or(xml.len(), |end| i + end + 1);
+        if tag.starts_with(&close) {
+            if depth > 0 {
+                depth -= 1;
+                if depth == 0 {
+                    ranges.push(start..tag_end);
+                }
+            }
+        }
+        else if tag.starts_with(&open) && tag[open.len()..].starts_with(['>', ' ', '/']) {
+            if depth == 0 {
+                start = i;
+            }
+            if xml[..tag_end].ends_with("/>") {
+                if depth == 0 {
+                    ranges.push(start..tag_end);
+                }
+            }
+            else {
+                depth += 1;
+            }
+        }
+        position = tag_end;
+    }
+
+    ranges
+}
+
+
+/// Escapes the XML special characters of ``text``.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+
+/// Replaces the XML entities of ``text`` with their characters.
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output += &rest[..start];
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let character = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32)
+        };
+        match character {
+            Some(character) => {
+                output.push(character);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output + rest
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Returns a minimal DOCX archive with the paragraphs ``body`` (WordprocessingML).
+    pub(crate) fn archive(body: &str) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file(CONTENT_TYPES_PATH, SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"<?xml version=\"1.0\"?><Types/>").unwrap();
+        writer.start_file(DOCUMENT_PATH, SimpleFileOptions::default()).unwrap();
+        writer.write_all(format!("<w:document><w:body>{body}</w:body></w:document>").as_bytes()).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Returns the document body of the DOCX ``archive``.
+    pub(crate) fn document(archive: &[u8]) -> String {
+        part(archive, DOCUMENT_PATH)
+    }
+
+    /// Returns the part at ``path`` of the DOCX ``archive``.
+    fn part(archive: &[u8], path: &str) -> String {
+        let mut content = String::new();
+        ZipArchive::new(Cursor::new(archive)).unwrap()
+            .by_name(path).unwrap()
+            .read_to_string(&mut content).unwrap();
+        content
+    }
+
+    fn template(body: &str) -> DocxTemplate {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), archive(body)).unwrap();
+        DocxTemplate::open(file.path()).unwrap()
+    }
+
+    fn auto_gen() -> Placeholder {
+        Placeholder { marker: "{AUTO_GEN}".to_string(), section: None }
+    }
+
+    #[test]
+    fn reads_text() {
+        let docx = template(
+            "<w:p><w:r><w:t>Mnenje za </w:t></w:r><w:r><w:t>{CANDIDATE}</w:t></w:r></w:p>\
+            <w:p><w:r><w:t xml:space=\"preserve\">A &amp; B</w:t></w:r></w:p>"
+        );
+        assert_eq!(docx.text(), "Mnenje za {CANDIDATE}\nA & B");
+    }
+
+    #[test]
+    fn substitutes_per_run() {
+        let mut docx = template(
+            "<w:p><w:r><w:rPr><w:b/></w:rPr><w:t>{CANDIDATE}</w:t></w:r><w:r><w:t>, kandidat</w:t></w:r></w:p>"
+        );
+        docx.substitute(|text| Ok(text.replace("{CANDIDATE}", "Novak & <Kos>"))).unwrap();
+        assert_eq!(
+            docx.document,
+            "<w:document><w:body><w:p>\
+            <w:r><w:rPr><w:b/></w:rPr><w:t xml:space=\"preserve\">Novak &amp; &lt;Kos&gt;</w:t></w:r>\
+            <w:r><w:t xml:space=\"preserve\">, kandidat</w:t></w:r>\
+            </w:p></w:body></w:document>"
+        );
+    }
+
+    #[test]
+    fn substitutes_split_marker() {
+        let mut docx = template(
+            "<w:p><w:r><w:t>Za {CAND</w:t></w:r><w:r><w:rPr><w:i/></w:rPr><w:t>IDATE}.</w:t></w:r></w:p>"
+        );
+        docx.substitute(|text| Ok(text.replace("{CANDIDATE}", "Novak"))).unwrap();
+        assert_eq!(docx.text(), "Za Novak.");
+        assert_eq!(
+            docx.document,
+            "<w:document><w:body><w:p>\
+            <w:r><w:t xml:space=\"preserve\">Za Novak.</w:t></w:r>\
+            <w:r><w:rPr><w:i/></w:rPr><w:t xml:space=\"preserve\"></w:t></w:r>\
+            </w:p></w:body></w:document>"
+        );
+    }
+
+    #[test]
+    fn renders_styles() {
+        let docx = template(
+            "<w:p><w:pPr><w:jc w:val=\"both\"/></w:pPr>\
+            <w:r><w:rPr><w:rFonts w:ascii=\"Arial\"/></w:rPr><w:t>{AUTO_</w:t></w:r><w:r><w:t>GEN}</w:t></w:r></w:p>\
+            <w:p><w:r><w:t>Konec</w:t></w:r></w:p>"
+        );
+        let generated = [(auto_gen(), vec!["Ocena je \\textbf{odlična} \\& <visoka>.".to_string(), "\\textit{Da}".to_string()])];
+        let document = document(&docx.render(&generated, 1).unwrap());
+        assert_eq!(
+            document,
+            "<w:document><w:body><w:p><w:pPr><w:jc w:val=\"both\"/></w:pPr>\
+            <w:r><w:rPr><w:rFonts w:ascii=\"Arial\"/></w:rPr><w:t xml:space=\"preserve\">Ocena je </w:t></w:r>\
+            <w:r><w:rPr><w:rFonts w:ascii=\"Arial\"/><w:b/></w:rPr><w:t xml:space=\"preserve\">odlična</w:t></w:r>\
+            <w:r><w:rPr><w:rFonts w:ascii=\"Arial\"/></w:rPr><w:t xml:space=\"preserve\"> &amp; &lt;visoka&gt;.</w:t></w:r>\
+            <w:r><w:rPr><w:rFonts w:ascii=\"Arial\"/></w:rPr><w:t xml:space=\"preserve\"> </w:t></w:r>\
+            <w:r><w:rPr><w:rFonts w:ascii=\"Arial\"/><w:i/></w:rPr><w:t xml:space=\"preserve\">Da</w:t></w:r>\
+            </w:p>\
+            <w:p><w:r><w:t>Konec</w:t></w:r></w:p></w:body></w:document>"
+        );
+    }
+
+    #[test]
+    fn records_seed() {
+        let rendered = template("<w:p><w:r><w:t>{AUTO_GEN}</w:t></w:r></w:p>").render(&[], 42).unwrap();
+        let properties = part(&rendered, CUSTOM_PROPERTIES_PATH);
+        assert!(properties.contains("pid=\"2\" name=\"CloggenSeed\"><vt:lpwstr>42</vt:lpwstr>"));
+        assert!(part(&rendered, CONTENT_TYPES_PATH).contains("PartName=\"/docProps/custom.xml\""));
+        assert!(part(&rendered, RELATIONSHIPS_PATH).contains("Target=\"docProps/custom.xml\""));
+
+        // Rendering the output again replaces the property
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), &rendered).unwrap();
+        let rerendered = DocxTemplate::open(file.path()).unwrap().render(&[], 7).unwrap();
+        let properties = part(&rerendered, CUSTOM_PROPERTIES_PATH);
+        assert_eq!(properties.matches("CloggenSeed").count(), 1);
+        assert!(properties.contains("<vt:lpwstr>7</vt:lpwstr>"));
+        assert_eq!(part(&rerendered, RELATIONSHIPS_PATH).matches("docProps/custom.xml").count(), 1);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "\\documentclass{article}").unwrap();
+        assert!(matches!(DocxTemplate::open(file.path()), Err(Error::Template(_))));
+    }
+}
@@ -0,0 +1,107 @@
+//! Error types of the Cloggen library.
+
+use std::path::PathBuf;
+use std::io;
+
+use crate::responses::Issue;
+use crate::compiler::{self, TexError};
+
+
+/// Result type used throughout the Cloggen library.
+pub type Result<T> = std::result::Result<T, Error>;
+
+
+/// Errors returned by Cloggen's public API.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A file could not be opened, read or written.
+    #[error("{context} ({})", path.display())]
+    Io {
+        context: String,
+        path: PathBuf,
+        #[source]
+        source: io::Error
+    },
+
+    /// The STUDIS export (CSV file or spreadsheet) is malformed or is missing data.
+    #[error("invalid STUDIS export: {0}")]
+    Survey(String),
+
+    /// The CSV reader failed on a STUDIS section.
+    #[error("could not parse CSV")]
+    Csv(#[from] csv::Error),
+
+    /// The responses file is not valid JSON.
+    #[error("responses file is not valid JSON")]
+    Json(#[from] serde_json::Error),
+
+    /// The responses file does not match the expected schema. Contains all the problems found.
+    #[error("invalid responses file:\n{}", join_lines(.0))]
+    ResponseSchema(Vec<Issue>),
+
+    /// The responses cannot be applied to the given grades.
+    #[error("invalid responses: {0}")]
+    Responses(String),
+
+    /// The output template is invalid.
+    #[error("invalid template: {0}")]
+    Template(String),
+
+    /// The LaTeX compiler failed. Contains the errors of the TeX log and the whole log (empty when unavailable).
+    #[error("LaTeX compilation failed{}", compile_details(.errors, .log))]
+    Compile {
+        errors: Vec<TexError>,
+        log: String,
+        #[source]
+        source: io::Error
+    },
+
+    /// The bundle of TeX support files cannot be used, or is missing files (e.g., packages) of the document.
+    #[error("TeX bundle: {0}")]
+    Bundle(String),
+
+    /// The manifest of the ``batch`` command is invalid.
+    #[error("invalid batch manifest: {0}")]
+    Manifest(String),
+
+    /// Invalid user input (e.g., file patterns that match too few files).
+    #[error("{0}")]
+    Input(String),
+}
+
+
+impl Error {
+    /// Creates an [`Error::Io`] with a description of the failed operation.
+    pub fn io(context: impl Into<String>, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io { context: context.into(), path: path.into(), source }
+    }
+
+    /// Creates an [`Error::Compile`], with the errors parsed from the TeX ``log``.
+    pub fn compile(source: io::Error, log: String) -> Self {
+        Self::Compile { errors: compiler::parse_log(&log), log, source }
+    }
+}
+
+
+/// Number of the log's last lines shown, when the log contains no errors.
+const LOG_TAIL_LINES: usize = 10;
+
+
+/// Lists the TeX ``errors`` or, when there are none, the end of the ``log``.
+fn compile_details(errors: &[TexError], log: &str) -> String {
+    if !errors.is_empty() {
+        return format!(":\n{}", join_lines(errors));
+    }
+
+    let lines: Vec<&str> = log.lines().collect();
+    match lines.len() {
+        0 => String::new(),
+        n => format!(", end of the log:\n{}", join_lines(&lines[n.saturating_sub(LOG_TAIL_LINES)..]))
+    }
+}
+
+
+/// Formats each of the ``items`` on its own indented line.
+fn join_lines<T: std::fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|item| format!("  {item}")).collect::<Vec<_>>().join("\n")
+}
@@ -1,57 +1,124 @@
 //! Module related to files
 
+use encoding_rs::Encoding;
+
 use std::io::{self, Read};
 use std::path::Path;
 use std::fs::File;
-use encoding_rs;
+
+use crate::error::{Error, Result};
+use crate::preproc::{COUNT_COLUMN, MEAN_COLUMN, QUESTION_COLUMN, STD_COLUMN};
 
 
 /// Encoding aware file read.
 /// This function reads the file at ``filepath`` and then tries to decode it,
-/// assuming multiple possible encodings.
+/// assuming multiple possible encodings (see [`decode_universal`]).
 pub fn read_file_universal<T: AsRef<Path>>(filepath: T) -> io::Result<String> {
+    read_file_encoding(filepath, None).map(|(data, _)| data)
+}
+
+
+/// Reads the file at ``filepath`` and decodes it with the ``encoding``. If ``encoding`` is ``None``, it is detected
+/// as in [`read_file_universal`]. Returns the decoded data and the used encoding.
+pub fn read_file_encoding<T: AsRef<Path>>(
+    filepath: T,
+    encoding: Option<&'static Encoding>
+) -> io::Result<(String, &'static Encoding)> {
     let filepath = filepath.as_ref();
     let mut file = File::open(filepath)?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf)?;
 
-    match encoding_rs::Encoding::for_bom(&buf) {
-        Some((encoding, _)) => {
-            let (decoded, _, _) = encoding.decode(&buf);  // Decode based on BOM (byte order mark)
-            return Ok(decoded.to_string());
+    if let Some(encoding) = encoding {
+        let (decoded, errors) = encoding.decode_with_bom_removal(&buf);
+        if errors {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the file is not valid {}", encoding.name())));
         }
-        None => {
-            // Encoding could not be determined through byte-order-marker
-            // => Try to decode with various standards.
-            let mut decoded = None;
-            for encoding in ENCODINGS {
-                // It's a bit inefficient to decode the entire file in the presence decoding errors,
-                // but currently the ``encoding_rs`` library lacks early stopping.
-                let (new, _, errors) = encoding.decode(&buf);
-                if !errors {
-                    decoded = Some(new);
-                    break;
-                }
-            }
-
-            // Returned the decoded data if found, else InvalidData error.
-            if let Some(data) = decoded {
-                return Ok(data.to_string());
-            }
-            else {
-                return Err(io::Error::from(io::ErrorKind::InvalidData))
-            }
+        return Ok((decoded.to_string(), encoding));
+    }
+
+    decode_universal(&buf).ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+
+/// Decodes the ``data`` of a file with an unknown encoding. The encoding is given by the BOM (byte order mark),
+/// if present. Otherwise, the data is decoded with each of the candidate encodings (UTF-8, UTF-16 and the common
+/// 8-bit code pages, e.g., Windows-1250 and ISO-8859-2) and the most plausible text is returned, with its encoding.
+/// Texts with Slovenian letters and the column names of STUDIS exports are preferred.
+/// Returns ``None`` if no encoding decodes the data without errors.
+pub fn decode_universal(data: &[u8]) -> Option<(String, &'static Encoding)> {
+    if let Some((encoding, _)) = Encoding::for_bom(data) {
+        let (decoded, _, _) = encoding.decode(data);  // Decode based on BOM (byte order mark)
+        return Some((decoded.into_owned(), encoding));
+    }
+
+    // Most 8-bit encodings decode any data without errors, so the decoded texts are compared instead.
+    // It's a bit inefficient to decode the entire file with each encoding, but the STUDIS exports are small.
+    let mut best: Option<(i64, String, &'static Encoding)> = None;
+    for encoding in ENCODINGS {
+        let (decoded, errors) = encoding.decode_without_bom_handling(data);
+        if errors {
+            continue;
+        }
+
+        // The first of equally plausible encodings is kept, e.g., UTF-8 for ASCII data.
+        let score = plausibility(&decoded);
+        if best.as_ref().is_none_or(|(best_score, ..)| score > *best_score) {
+            best = Some((score, decoded.into_owned(), encoding));
         }
     }
+
+    best.map(|(_, decoded, encoding)| (decoded, encoding))
+}
+
+
+/// Scores how plausible the ``text`` is as a decoded STUDIS export (in Slovenian). The Slovenian letters and the
+/// column names of the export (e.g., ``Vprašanje``) add to the score, while the characters that are unlikely
+/// in Slovenian text (e.g., control characters, letters of other alphabets) are penalized. Wrongly decoded
+/// text usually contains such characters in place of the letters č, š and ž.
+fn plausibility(text: &str) -> i64 {
+    const KEYWORD_SCORE: i64 = 50;
+
+    let characters: i64 = text.chars()
+        .map(|c| match c {
+            'č' | 'š' | 'ž' | 'Č' | 'Š' | 'Ž' => 5,
+            'ć' | 'đ' | 'Ć' | 'Đ' => 1,
+            '\t' | '\n' | '\r' => 0,
+            '\u{FFFD}' => -20,  // Replacement character
+            c if c.is_ascii() && c.is_control() => -20,
+            c if c.is_ascii() => 1,
+            c if c.is_control() => -20,
+            // Typographic punctuation, e.g., quotes and dashes
+            '\u{A0}' | '–' | '—' | '„' | '“' | '”' | '‘' | '’' | '…' | '°' | '€' => 0,
+            c if c.is_alphabetic() && (c as u32) < 0x250 => -3,  // Other letters of the Latin alphabet
+            c if c.is_alphabetic() => -10,
+            _ => -3
+        })
+        .sum();
+
+    let keywords: i64 = [QUESTION_COLUMN, COUNT_COLUMN, MEAN_COLUMN, STD_COLUMN].iter()
+        .map(|keyword| text.matches(keyword).count() as i64 * KEYWORD_SCORE)
+        .sum();
+
+    characters + keywords
 }
 
 
-/// Table of encodings to try (in order) when the BOM is not present. 
-static ENCODINGS: [&'static encoding_rs::Encoding; 11] = [
+/// Parses the name of an encoding (e.g., ``windows-1250`` or ``utf-8``), as given by the user.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| Error::Input(format!(
+        "unknown encoding \"{label}\" (expected e.g. utf-8, windows-1250 or iso-8859-2)"
+    )))
+}
+
+
+/// Table of encodings to compare when the BOM is not present. The first of equally plausible encodings is used.
+static ENCODINGS: [&Encoding; 14] = [
     encoding_rs::UTF_8,
     encoding_rs::WINDOWS_1250,
-    encoding_rs::WINDOWS_1251,
+    encoding_rs::ISO_8859_2,
     encoding_rs::WINDOWS_1252,
+    encoding_rs::WINDOWS_1251,
     encoding_rs::WINDOWS_1253,
     encoding_rs::WINDOWS_1254,
     encoding_rs::WINDOWS_1255,
@@ -59,4 +126,52 @@ static ENCODINGS: [&'static encoding_rs::Encoding; 11] = [
     encoding_rs::WINDOWS_1257,
     encoding_rs::WINDOWS_1258,
     encoding_rs::WINDOWS_874,
+    encoding_rs::UTF_16LE,
+    encoding_rs::UTF_16BE,
 ];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Beginning of a STUDIS export, as in ``anketa.csv``.
+    const EXPORT: &str = "Anketa o izvajalcu\t\t\t
+Vprašanje\tŠtevilo odgovorov\tPovprečje\tStandardni odklon
+Snov podaja na razumljiv način.\t30\t4,7\t0,65
+Zna vzbuditi zanimanje za predmet, ki ga uči že več let.\t30\t4,67\t0,61
+";
+
+    fn detect(data: &[u8]) -> (String, &'static str) {
+        let (decoded, encoding) = decode_universal(data).unwrap();
+        (decoded, encoding.name())
+    }
+
+    #[test]
+    fn detects_code_pages() {
+        for encoding in [encoding_rs::UTF_8, encoding_rs::WINDOWS_1250, encoding_rs::ISO_8859_2] {
+            let (data, _, errors) = encoding.encode(EXPORT);
+            assert!(!errors);
+            assert_eq!(detect(&data), (EXPORT.to_string(), encoding.name()));
+        }
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        let little: Vec<u8> = EXPORT.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(detect(&little), (EXPORT.to_string(), "UTF-16LE"));
+
+        let big: Vec<u8> = EXPORT.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(detect(&big), (EXPORT.to_string(), "UTF-16BE"));
+    }
+
+    #[test]
+    fn prefers_bom_and_utf8() {
+        let mut data = vec![0xEF, 0xBB, 0xBF];
+        data.extend_from_slice(EXPORT.as_bytes());
+        assert_eq!(detect(&data), (EXPORT.to_string(), "UTF-8"));
+
+        // ASCII data is valid in all the 8-bit encodings
+        assert_eq!(detect(b"Anketa o predmetu\n"), ("Anketa o predmetu\n".to_string(), "UTF-8"));
+    }
+}
@@ -2,9 +2,19 @@
 use egui::{Color32, FontId, Frame, IconData, Id, PopupAnchor, RichText, Stroke, ViewportBuilder};
 use eframe::{egui};
 
+use cloggen::{compiler, config, create, merge, placeholders};
+use cloggen::preproc::{SurveyExport, SURVEY_EXTENSIONS};
+use cloggen::responses::ResponseSet;
+use cloggen::merge::{MergeWeighting, StdMode};
+use cloggen::placeholders::Variables;
+use cloggen::create::OutputFormat;
+
 use std::time::Instant;
 use std::path::PathBuf;
 use std::ops::BitAnd;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
 
 /// How many milliseconds to wait before showing a cancellation button.
 const CANCEL_OP_SHOW_WAIT_MS: u128 = 5000;
@@ -42,7 +52,9 @@ pub fn main_gui() {
 #[derive(Default)]
 struct Cloggen {
     menu: UiMenu,
-    menu_state: UiMenuState
+    menu_state: UiMenuState,
+    /// Creates the reports in the background. Started with the first report.
+    worker: Option<ReportWorker>
 }
 
 impl eframe::App for Cloggen {
@@ -71,8 +83,8 @@ impl eframe::App for Cloggen {
                     });
                 }
                 UiMenuState::NewReport {
-                    csv_file , responses_file, tex_template,
-                    message, open_on_success, state
+                    csv_file , responses_file, tex_template, format, bundle, seed, variables,
+                    message, details, open_on_success, state
                 } => {
                     match state {
                         NewReportState::LatexProcessing { handle: maybe_handle, start_time } => {
@@ -87,18 +99,28 @@ impl eframe::App for Cloggen {
                                     );
                                 }
                             });
-                            if let Some(handle) = maybe_handle && handle.is_finished() {
-                                match maybe_handle.take().unwrap().join() {
+                            if let Some(Ok(result)) = maybe_handle.as_ref().map(|receiver| receiver.try_recv()) {
+                                maybe_handle.take();
+                                match result {
                                     Ok(call_result) => {
                                         match call_result {
-                                            Ok(filename) => {
-                                                *message = format!("Datoteka je bila shranjena: {filename}");
+                                            Ok(report) => {
+                                                *message = format!(
+                                                    "Datoteka je bila shranjena: {} (seme {})",
+                                                    report.path.display(), report.seed
+                                                );
                                                 if *open_on_success {
                                                     // Open, ignore errors
-                                                    let _ = open::that(filename);
+                                                    let _ = open::that(report.path);
                                                 }
                                             },
-                                            Err(err) => *message = format!("Napaka: {err}")
+                                            Err(err) => {
+                                                *message = format!("Napaka: {err}");
+                                                // The whole TeX log, for the details pane
+                                                if let cloggen::Error::Compile { log, .. } = &err {
+                                                    *details = log.clone();
+                                                }
+                                            }
                                          }
                                     }
                                     Err(panic_err) => {
@@ -119,36 +141,80 @@ impl eframe::App for Cloggen {
 
                                 // Vhod
                                 ui.add_space(10.0);
-                                file_input(csv_file, ui, "STUDIS CSV", "csv");
-                                file_input(responses_file, ui, "JSON nabor odzivov", "json");
-                                file_input(tex_template, ui, "LaTeX predloga", "tex");
+                                file_input(csv_file, ui, "STUDIS anketa (CSV ali preglednica)", &SURVEY_EXTENSIONS);
+                                file_input(responses_file, ui, "JSON nabor odzivov", &["json"]);
+                                file_input(tex_template, ui, "Predloga", template_extensions(format));
+                                if *format == OutputFormat::Pdf {
+                                    file_input(bundle, ui, "Lokalni TeX paket (neobvezno, za delo brez povezave)", &["zip", "tar"]);
+                                }
+                                ui.horizontal(|ui| {
+                                    ui.label("Izhodni format:");
+                                    egui::ComboBox::from_id_salt("format")
+                                        .selected_text(format_as_str(format))
+                                        .show_ui(ui, |ui| {
+                                            for option in [
+                                                OutputFormat::Pdf, OutputFormat::Latex, OutputFormat::Markdown,
+                                                OutputFormat::Html, OutputFormat::Txt, OutputFormat::Docx
+                                            ] {
+                                                let label = format_as_str(&option);
+                                                ui.selectable_value(format, option, label);
+                                            }
+                                        });
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label("Seme (neobvezno):");
+                                    ui.text_edit_singleline(seed)
+                                        .on_hover_text("Enako seme ob enakih vhodih ustvari enako mnenje.");
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label("Spremenljivke (neobvezno):");
+                                    ui.add(egui::TextEdit::multiline(variables).desired_rows(3).hint_text("CANDIDATE=Janez Novak"))
+                                        .on_hover_text("Ena spremenljivka na vrstico v obliki IME=vrednost. \
+                                            V odzivih in predlogi zamenja oznako {IME}.");
+                                });
 
                                 ui.add_space(50.0);
                                 ui.vertical_centered(|ui| {
                                     if ui.button(
                                         RichText::new("Ustvari in shrani")
                                             .font(FontId::proportional(24.0))
-                                    ).clicked() {
-                                        if let Some(path) = rfd::FileDialog::new()
-                                            .add_filter("PDF", &["pdf"])
+                                    ).clicked() && let Some(path) = rfd::FileDialog::new()
+                                            .add_filter(format.extension().to_uppercase(), &[format.extension()])
                                             .save_file()
-                                        {
-
-                                            let csv_file = csv_file.clone();
-                                            let responses = responses_file.clone();
-                                            let tex = tex_template.clone();
-                                            let handle = Some(std::thread::spawn(move || {
-                                                super::create::command_create(
-                                                    &csv_file,
-                                                    &responses,
-                                                    &tex,
-                                                    super::config::create::SECTION_DEFAULT,
-                                                    &super::config::create::FORMAT_DEFAULT,
-                                                    &Some(path.clone())
-                                                )
-                                            }));
-                                            *state = NewReportState::LatexProcessing { handle, start_time: Instant::now() };
-                                        };
+                                    {
+                                        details.clear();
+                                        // Validate the responses before starting the (slow) compilation,
+                                        // so that all problems of the file are shown at once.
+                                        match (
+                                            ResponseSet::from_file(responses_file),
+                                            parse_seed(seed),
+                                            parse_variables(variables)
+                                        ) {
+                                            (Err(err), _, _) => *message = format!("Napaka: {err}"),
+                                            (_, Err(err), _) => *message = format!("Napaka: neveljavno seme ({err})"),
+                                            (_, _, Err(err)) => *message = format!("Napaka: {err}"),
+                                            (Ok(_), Ok(seed), Ok(variables)) => {
+                                                let csv_file = csv_file.clone();
+                                                let responses = responses_file.clone();
+                                                let tex = tex_template.clone();
+                                                let format = format.clone();
+                                                let bundle = (!bundle.as_os_str().is_empty()).then(|| bundle.clone());
+                                                let handle = Some(self.worker.get_or_insert_with(ReportWorker::spawn).create(ReportJob {
+                                                    csv_file,
+                                                    responses,
+                                                    template: tex,
+                                                    options: create::CreateOptions {
+                                                        output_filepath: Some(path),
+                                                        format,
+                                                        seed,
+                                                        compile: compiler::CompileOptions { bundle, offline: false },
+                                                        variables,
+                                                        ..Default::default()
+                                                    }
+                                                }));
+                                                *state = NewReportState::LatexProcessing { handle, start_time: Instant::now() };
+                                            }
+                                        }
                                     };
                                     ui.checkbox(open_on_success, "Odpri ob uspehu");
                                 });
@@ -157,27 +223,36 @@ impl eframe::App for Cloggen {
                                 if !message.is_empty() {
                                     ui.label(message.as_str());
                                 }
+                                if !details.is_empty() {
+                                    egui::CollapsingHeader::new("Podrobnosti (LaTeX dnevnik)").show(ui, |ui| {
+                                        egui::ScrollArea::vertical().max_height(250.0).show(ui, |ui| {
+                                            ui.add(egui::TextEdit::multiline(&mut details.as_str())
+                                                .code_editor()
+                                                .desired_width(f32::INFINITY));
+                                        });
+                                    });
+                                }
                             });
                         }
                     }
                     
                 }
-                UiMenuState::MergeCsv { csv_files, selected_files, message } => {
+                UiMenuState::MergeCsv { csv_files, selected_files, weighting, std_mode, message } => {
                     ui.vertical_centered(|ui| {
                         ui.heading("Združevanje CSV podatkov iz STUDIS anket");
                         
                         // Control panel
                         egui::TopBottomPanel::top("top").show_inside(ui, |ui| {
                             ui.horizontal_wrapped(|ui| {
-                                if ui.button("Dodaj datoteke").clicked() {
-                                    if let Some(files) = rfd::FileDialog::new().add_filter("CSV (več datotek)", &["csv"]).pick_files() {
-                                        if csv_files.len() + files.len() <= MAX_MERGE_FILES {
-                                            csv_files.extend(files);
-                                            *message = "".to_string();
-                                        }
-                                        else {
-                                            *message = format!("Napaka: Dovoljenih je največ {MAX_MERGE_FILES} datotek.");
-                                        }
+                                if ui.button("Dodaj datoteke").clicked()
+                                    && let Some(files) = rfd::FileDialog::new().add_filter("CSV ali preglednice (več datotek)", &SURVEY_EXTENSIONS).pick_files()
+                                {
+                                    if csv_files.len() + files.len() <= MAX_MERGE_FILES {
+                                        csv_files.extend(files);
+                                        *message = "".to_string();
+                                    }
+                                    else {
+                                        *message = format!("Napaka: Dovoljenih je največ {MAX_MERGE_FILES} datotek.");
                                     }
                                 }
                                 if ui.button("Odstrani izbiro").clicked() {
@@ -203,20 +278,47 @@ impl eframe::App for Cloggen {
                                     *csv_files = new_files;
                                 }
 
+                                egui::ComboBox::from_id_salt("weighting")
+                                    .selected_text(weighting_as_str(*weighting))
+                                    .show_ui(ui, |ui| {
+                                        for option in [MergeWeighting::Equal, MergeWeighting::Respondents] {
+                                            ui.selectable_value(weighting, option, weighting_as_str(option));
+                                        }
+                                    });
+                                egui::ComboBox::from_id_salt("std_mode")
+                                    .selected_text(std_mode_as_str(*std_mode))
+                                    .show_ui(ui, |ui| {
+                                        for option in [StdMode::Means, StdMode::Pooled] {
+                                            ui.selectable_value(std_mode, option, std_mode_as_str(option));
+                                        }
+                                    });
+
                                 const MERGE_BNT_TEXT: &str = "Združi vse datoteke";
                                 if csv_files.len() > 1 {  // Needs at least two files to merge
-                                    if ui.button(MERGE_BNT_TEXT).clicked() {
-                                        if let Some(file) = rfd::FileDialog::new().add_filter("CSV", &["csv"]).save_file() {
-                                            match super::merge::command_merge(
-                                                &csv_files,
-                                                super::config::merge::SECTION_DEFAULT,
-                                                &file
-                                            ) {
-                                                Ok(()) => *message = format!("Datoteka je shranjena: {}", file.display()),
-                                                Err(e) => *message = format!("Napaka: {e}")
+                                    if ui.button(MERGE_BNT_TEXT).clicked()
+                                        && let Some(file) = rfd::FileDialog::new().add_filter("CSV", &["csv"]).save_file()
+                                    {
+                                        match merge::command_merge(
+                                            csv_files,
+                                            &file,
+                                            &merge::MergeOptions {
+                                                weighting: *weighting,
+                                                std_mode: *std_mode,
+                                                ..Default::default()
                                             }
-                                        };
-                                    };   
+                                        ) {
+                                            Ok(matches) => {
+                                                *message = format!("Datoteka je shranjena: {}", file.display());
+                                                for matched in matches {
+                                                    *message += &format!(
+                                                        "\nZdruženo \"{}\" ({}) z \"{}\"",
+                                                        matched.question, matched.file.display(), matched.merged_question
+                                                    );
+                                                }
+                                            },
+                                            Err(e) => *message = format!("Napaka: {e}")
+                                        }
+                                    };
                                 }
                                 else {
                                     // Display a button with grayed out text and set the cursor to the denied symbol
@@ -266,7 +368,7 @@ impl eframe::App for Cloggen {
     }
 }
 
-fn file_input(file_var: &mut PathBuf, ui: &mut egui::Ui, heading: &str, extension: &str) {
+fn file_input(file_var: &mut PathBuf, ui: &mut egui::Ui, heading: &str, extensions: &[&str]) {
     Frame::new()
         .stroke(Stroke::new(1.0, Color32::WHITE))
         .inner_margin(5.0).show(ui, |ui|
@@ -274,10 +376,10 @@ fn file_input(file_var: &mut PathBuf, ui: &mut egui::Ui, heading: &str, extensio
         ui.heading(heading);
         ui.columns(2, |ui| {
             let button = ui[0].button("Izberi datoteko");
-            if button.clicked() {
-                if let Some(path) = rfd::FileDialog::new().add_filter(extension.to_uppercase(), &[extension]).pick_file() {
-                    *file_var = path;
-                }
+            if button.clicked()
+                && let Some(path) = rfd::FileDialog::new().add_filter(extensions.join(", ").to_uppercase(), extensions).pick_file()
+            {
+                *file_var = path;
             }
 
             let csv_file = file_var.as_os_str().to_string_lossy();
@@ -296,13 +398,21 @@ enum UiMenuState {
         csv_file: PathBuf,
         responses_file: PathBuf,
         tex_template: PathBuf,
+        format: OutputFormat,
+        bundle: PathBuf,
+        seed: String,
+        variables: String,
         message: String,
+        /// Details of the last error (the TeX log).
+        details: String,
         open_on_success: bool,
         state: NewReportState
     },
     MergeCsv {
         csv_files: Vec<PathBuf>,
         selected_files: u32,
+        weighting: MergeWeighting,
+        std_mode: StdMode,
         message: String,
     }
 }
@@ -333,11 +443,22 @@ impl UiMenu {
                 csv_file: PathBuf::new(),
                 responses_file: PathBuf::new(),
                 tex_template: PathBuf::new(),
+                format: config::create::FORMAT_DEFAULT,
+                bundle: PathBuf::new(),
+                seed: String::new(),
+                variables: String::new(),
                 message: String::new(),
+                details: String::new(),
                 open_on_success: false,
                 state: NewReportState::UserInput
             },
-            MergeCsv => UiMenuState::MergeCsv { csv_files: vec![], selected_files: 0, message: String::new() }
+            MergeCsv => UiMenuState::MergeCsv {
+                csv_files: vec![],
+                selected_files: 0,
+                weighting: config::merge::WEIGHTING_DEFAULT,
+                std_mode: config::merge::STD_MODE_DEFAULT,
+                message: String::new()
+            }
         }
     }
 }
@@ -351,7 +472,114 @@ enum NewReportState {
     /// The LaTeX code is compiling or the compiler
     /// is downloading packages.
     LatexProcessing {
-        handle: Option<std::thread::JoinHandle<anyhow::Result<String>>>,
+        handle: Option<mpsc::Receiver<thread::Result<cloggen::Result<create::Report>>>>,
         start_time: Instant
     }
 }
+
+
+/// A report to create in the background.
+struct ReportJob {
+    csv_file: PathBuf,
+    responses: PathBuf,
+    template: PathBuf,
+    options: create::CreateOptions
+}
+
+
+/// Background thread that creates the reports. It keeps the LaTeX compiler (see [`compiler::Compiler`])
+/// between reports, so that creating a report again is faster than the first time.
+struct ReportWorker {
+    jobs: mpsc::Sender<(ReportJob, mpsc::Sender<thread::Result<cloggen::Result<create::Report>>>)>
+}
+
+impl ReportWorker {
+    fn spawn() -> Self {
+        let (jobs, receiver) = mpsc::channel::<(ReportJob, mpsc::Sender<_>)>();
+        thread::spawn(move || {
+            let mut compiler = compiler::Compiler::new(compiler::CompileOptions::default());
+            for (job, result) in receiver {
+                if compiler.options() != &job.options.compile {
+                    compiler = compiler::Compiler::new(job.options.compile.clone());
+                }
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let survey = SurveyExport::from_file(&job.csv_file, &job.options.survey)?;
+                    create::generate_document(&survey, &job.responses, &job.template, &job.options)?.finish(&mut compiler)
+                }));
+                if outcome.is_err() {
+                    // The compiler may be left in an inconsistent state
+                    compiler = compiler::Compiler::new(job.options.compile.clone());
+                }
+                let _ = result.send(outcome);
+            }
+        });
+        Self { jobs }
+    }
+
+    /// Queues the ``job`` and returns the receiver of its result (or of the panic that occurred).
+    fn create(&self, job: ReportJob) -> mpsc::Receiver<thread::Result<cloggen::Result<create::Report>>> {
+        let (sender, receiver) = mpsc::channel();
+        // The worker stops only when it is dropped
+        self.jobs.send((job, sender)).expect("report worker stopped");
+        receiver
+    }
+}
+
+
+/// Label of the output ``format`` option.
+fn format_as_str(format: &OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Latex => "LaTeX",
+        OutputFormat::Pdf => "PDF",
+        OutputFormat::Markdown => "Markdown",
+        OutputFormat::Html => "HTML",
+        OutputFormat::Txt => "Navadno besedilo",
+        OutputFormat::Docx => "Word (DOCX)",
+    }
+}
+
+
+/// Extensions of the templates for the output ``format``.
+fn template_extensions(format: &OutputFormat) -> &'static [&'static str] {
+    match format {
+        OutputFormat::Latex | OutputFormat::Pdf => &["tex"],
+        OutputFormat::Markdown => &["md"],
+        OutputFormat::Html => &["html", "htm"],
+        OutputFormat::Txt => &["txt"],
+        OutputFormat::Docx => &["docx"],
+    }
+}
+
+
+/// Label of the merge ``weighting`` option.
+fn weighting_as_str(weighting: MergeWeighting) -> &'static str {
+    match weighting {
+        MergeWeighting::Equal => "Enakovredne datoteke",
+        MergeWeighting::Respondents => "Uteženo s številom odgovorov",
+    }
+}
+
+
+/// Label of the merge ``std_mode`` option.
+fn std_mode_as_str(std_mode: StdMode) -> &'static str {
+    match std_mode {
+        StdMode::Means => "Odklon povprečij datotek",
+        StdMode::Pooled => "Skupni odklon vseh odgovorov",
+    }
+}
+
+
+/// Parses the optional seed of the response selection. An empty ``text`` means a random seed.
+fn parse_seed(text: &str) -> Result<Option<u64>, std::num::ParseIntError> {
+    let text = text.trim();
+    if text.is_empty() { Ok(None) } else { text.parse().map(Some) }
+}
+
+
+/// Parses the user variables, given as ``NAME=value`` lines. Empty lines are skipped.
+fn parse_variables(text: &str) -> cloggen::Result<Variables> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(placeholders::parse_variable)
+        .collect()
+}
@@ -0,0 +1,56 @@
+//! # CLOGGEN
+//! Generator študentskih mnenj (za habilitacijo).
+//!
+//! Knjižnica ponuja vse korake, ki jih uporablja ukaz ``cloggen``, tako da jih je mogoče
+//! klicati tudi iz lastnih orodij:
+//!
+//! - branje STUDIS anket (CSV datotek ali preglednic): [`preproc::SurveyExport`] (sekcije z vprašanji in ocenami)
+//!   z možnostmi branja [`preproc::SurveyOptions`] in pregledom prebranega z [`preproc::SurveyExport::inspect`],
+//! - izbira odzivov: [`responses::ResponseSet`] in [`create::select_responses`],
+//! - preverjanje odzivov in predloge: [`lint::command_lint`],
+//! - vstavljanje v predlogo: [`create::load_template`], [`create::substitute_template`] in [`create::render_template`]
+//!   (oznake, kot je ``{MEAN:1}``, so v [`placeholders`]),
+//! - pretvorba LaTeX oblikovanja odzivov v Markdown, HTML ali navadno besedilo: [`markup::convert_latex`],
+//! - vstavljanje v DOCX predlogo: [`docx::DocxTemplate`],
+//! - prevajanje LaTeX-a: [`compiler::compile_latex`], [`compiler::Compiler`] za zaporedno prevajanje več dokumentov
+//!   v istem procesu in [`compiler::compile_parallel`] za hkratno prevajanje,
+//! - združevanje anket: [`merge::command_merge`],
+//! - generiranje mnenj za več kandidatov: [`batch::command_batch`].
+//!
+//! Vse funkcije vračajo [`Result`] z napako tipa [`Error`].
+//!
+//! ## Primer
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use cloggen::create::{command_create, CreateOptions};
+//!
+//! let report = command_create(
+//!     Path::new("anketa.csv"),
+//!     Path::new("odzivi.json"),
+//!     Path::new("mnenje.tex"),
+//!     &CreateOptions::default()
+//! )?;
+//! println!("{} (seme {})", report.path.display(), report.seed);
+//! # Ok::<(), cloggen::Error>(())
+//! ```
+//!
+//! Navodila za uporabo ukazne vrstice so v datoteki ``README.md``.
+
+pub mod compiler;
+pub mod preproc;
+pub mod config;
+pub mod create;
+pub mod responses;
+pub mod placeholders;
+pub mod matching;
+pub mod diversity;
+pub mod markup;
+pub mod docx;
+pub mod merge;
+pub mod batch;
+pub mod lint;
+pub mod error;
+pub mod fs;
+
+pub use error::{Error, Result};
@@ -0,0 +1,300 @@
+//! Module of the ``lint`` command.
+//!
+//! The command checks the responses file (and optionally a STUDIS export and a template) for all the problems
+//! that would otherwise only surface one at a time, when generating a report.
+
+use serde_json as sj;
+
+use std::path::Path;
+use std::fs::{self, File};
+
+use crate::create;
+use crate::docx::DocxTemplate;
+use crate::config;
+use crate::error::{Error, Result};
+use crate::placeholders::{self, Variables};
+use crate::matching::{self, MatchKind};
+use crate::preproc::{SurveyExport, SurveyOptions};
+use crate::responses::{self, Issue, ResponseSet, QUESTIONS_KEY};
+
+
+/// Characters that must always be escaped in LaTeX text.
+const C_LATEX_SPECIAL: [char; 3] = ['%', '&', '#'];
+/// Characters that must be escaped in LaTeX text, outside of math mode.
+const C_LATEX_SPECIAL_TEXT: [char; 2] = ['_', '^'];
+
+
+/// Options of the ``lint`` command.
+#[derive(Clone, Debug)]
+pub struct LintOptions {
+    /// Name of the default grades section (see [`create::CreateOptions::section`]).
+    pub section: String,
+    /// User variables that will be given when generating the report. Only the names are used.
+    pub variables: Variables,
+    /// Minimum similarity of question texts for a fuzzy match (see [`create::CreateOptions::similarity`]).
+    pub similarity: f64,
+    /// Options of reading the STUDIS export (e.g., its encoding).
+    pub survey: SurveyOptions
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            section: config::create::SECTION_DEFAULT.to_string(),
+            variables: Variables::new(),
+            similarity: config::create::SIMILARITY_DEFAULT,
+            survey: SurveyOptions::default()
+        }
+    }
+}
+
+
+/// Problems found by the ``lint`` command.
+#[derive(Clone, Debug, Default)]
+pub struct LintReport {
+    /// Problems that would make the report generation fail or produce a broken document.
+    pub errors: Vec<Issue>,
+    /// Suspicious parts that are allowed (e.g., CSV questions without responses).
+    pub warnings: Vec<Issue>
+}
+
+
+/// Parses the name of a user variable, given as ``NAME`` or ``NAME=value`` (the value is ignored).
+pub fn parse_variable_name(text: &str) -> Result<(String, String)> {
+    let name = text.split_once('=').map_or(text, |(name, _)| name).trim();
+    placeholders::validate_variable_name(name)?;
+    Ok((name.to_string(), String::new()))
+}
+
+
+/// Command processing function for the ``lint`` command.
+/// Checks the ``responses_filepath`` and, if given, its match with the STUDIS export ``studis_csv_filepath``
+/// and the ``tex_template_filepath``.
+pub fn command_lint(
+    response_json_filepath: &Path,
+    studis_csv_filepath: Option<&Path>,
+    tex_template_filepath: Option<&Path>,
+    options: &LintOptions
+) -> Result<LintReport> {
+    matching::validate_similarity(options.similarity)?;
+    let file = File::open(response_json_filepath)
+        .map_err(|e| Error::io("could not open responses file", response_json_filepath, e))?;
+    let value: sj::Value = sj::from_reader(file)?;
+
+    let mut report = LintReport::default();
+    let set = ResponseSet::parse(&value, &mut report.errors);
+
+    // Placeholders and LaTeX of the responses
+    for section in &set.sections {
+        for question in &section.questions {
+            for bucket in &question.buckets {
+                for (i, response) in bucket.responses.iter().enumerate() {
+                    let path = format!("{}[{i}]", bucket.path);
+                    check_placeholders(response, &path, false, &options.variables, &mut report);
+                    check_latex(response, &path, &mut report.errors);
+                }
+            }
+        }
+    }
+
+    // Questions of the responses and the STUDIS export
+    let survey = studis_csv_filepath.map(|path| SurveyExport::from_file(path, &options.survey)).transpose()?;
+    if let (Some(csv), Some(survey)) = (studis_csv_filepath, &survey) {
+        for section in &set.sections {
+            let title = section.title.as_deref().unwrap_or(&options.section);
+            let spath = section.title.as_deref().map_or("$".to_string(), |t| responses::json_path("$", t));
+            let csv_questions = match survey.section(title) {
+                Ok(survey_section) => survey_section.questions(),
+                Err(e) => {
+                    report.errors.push(Issue {
+                        path: spath,
+                        message: format!("section \"{title}\" cannot be read from {} ({e})", csv.display())
+                    });
+                    continue;
+                }
+            };
+
+            let qpath = responses::json_path(&spath, QUESTIONS_KEY);
+            let mut matched = vec![false; csv_questions.len()];
+            for question in &section.questions {
+                let path = responses::json_path(&qpath, &question.text);
+                match matching::find(&csv_questions, &question.text, &question.aliases, options.similarity) {
+                    Some((i, _)) if matched[i] => report.errors.push(Issue {
+                        path,
+                        message: format!(
+                            "matched to question \"{}\" of {}, which is already matched by another question",
+                            csv_questions[i], csv.display()
+                        )
+                    }),
+                    Some((i, kind)) => {
+                        matched[i] = true;
+                        if kind != MatchKind::Exact {
+                            report.warnings.push(Issue {
+                                path,
+                                message: format!("matched to question \"{}\" of {} ({kind})", csv_questions[i], csv.display())
+                            });
+                        }
+                    }
+                    None => report.errors.push(Issue {
+                        path,
+                        message: match matching::closest(&csv_questions, &question.text) {
+                            Some((closest, similarity)) => format!(
+                                "question is not in section \"{title}\" of {} (closest: \"{closest}\", similarity {similarity:.2})",
+                                csv.display()
+                            ),
+                            None => format!("question is not in section \"{title}\" of {}", csv.display())
+                        }
+                    })
+                }
+            }
+            for (question, _) in csv_questions.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+                report.warnings.push(Issue {
+                    path: format!("{} [{title}]", csv.display()),
+                    message: format!("question \"{question}\" has no responses")
+                });
+            }
+        }
+    }
+
+    // Placeholders of the template
+    if let Some(tex) = tex_template_filepath {
+        // The lines of a DOCX template are its paragraphs
+        let template = if tex.extension().is_some_and(|e| e.eq_ignore_ascii_case("docx")) {
+            DocxTemplate::open(tex)?.text()
+        }
+        else {
+            fs::read_to_string(tex).map_err(|e| Error::io("could not read tex file", tex, e))?
+        };
+        lint_template(&template, tex, &set, survey.as_ref(), options, &mut report);
+    }
+
+    Ok(report)
+}
+
+
+/// Checks the placeholders of the ``template`` (read from ``tex``).
+fn lint_template(
+    template: &str,
+    tex: &Path,
+    set: &ResponseSet,
+    survey: Option<&SurveyExport>,
+    options: &LintOptions,
+    report: &mut LintReport
+) {
+    let generated = create::template_placeholders(template);
+    if generated.is_empty() {
+        report.errors.push(Issue {
+            path: tex.display().to_string(),
+            message: "does not mark the location of the generated content with {AUTO_GEN}".to_string()
+        });
+    }
+
+    for placeholder in generated {
+        let line = template.lines().position(|l| l.contains(&placeholder.marker)).unwrap_or_default() + 1;
+        let path = format!("{}:{line}", tex.display());
+        let section = placeholder.section.as_deref().unwrap_or(&options.section);
+        if set.section(placeholder.section.as_deref(), &options.section).is_none() {
+            report.errors.push(Issue {
+                path: path.clone(),
+                message: format!("{} has no responses for section \"{section}\"", placeholder.marker)
+            });
+        }
+        if let Some(survey) = survey && survey.section(section).is_err() {
+            report.errors.push(Issue {
+                path,
+                message: format!("{} uses section \"{section}\", which is not in the STUDIS export", placeholder.marker)
+            });
+        }
+    }
+
+    for (i, line) in template.lines().enumerate() {
+        check_placeholders(line, &format!("{}:{}", tex.display(), i + 1), true, &options.variables, report);
+    }
+}
+
+
+/// Checks that the placeholders of ``text`` are known and have valid arguments.
+/// The known placeholders differ between the ``template`` and the responses. Unknown names in arguments of
+/// LaTeX commands of the template (e.g., ``\section{INTRO}``) are not placeholders and are not reported.
+fn check_placeholders(text: &str, path: &str, template: bool, variables: &Variables, report: &mut LintReport) {
+    let _ = placeholders::substitute_at(text, |name, arg, preceding| {
+        let (arg, _) = placeholders::split_raw(arg);
+        let known = match name {
+            placeholders::SECTION => true,
+            placeholders::AUTO_GEN => template,
+            placeholders::MEAN | placeholders::STD if !template => {
+                if let Err(e) = placeholders::format_number(name, 0.0, 0, arg) {
+                    report.errors.push(Issue { path: path.to_string(), message: e.to_string() });
+                }
+                true
+            }
+            placeholders::COUNT | placeholders::QUESTION => !template,
+            _ => variables.contains_key(name) || (template && is_command_argument(preceding))
+        };
+        if !known {
+            report.warnings.push(Issue {
+                path: path.to_string(),
+                message: format!("unknown placeholder {{{name}}} is left unchanged")
+            });
+        }
+        Ok(None)
+    });
+}
+
+
+/// Checks whether a brace group that follows the ``preceding`` text is an argument of a LaTeX command
+/// (e.g., ``\section{INTRO}``, ``\section*{INTRO}`` or ``\includegraphics[width=3cm]{LOGO}``).
+fn is_command_argument(preceding: &str) -> bool {
+    preceding.ends_with([']', '}'])
+        || preceding.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '*').ends_with('\\')
+}
+
+
+/// Checks that the LaTeX special characters of the response ``text`` are escaped.
+fn check_latex(text: &str, path: &str, errors: &mut Vec<Issue>) {
+    // Placeholders may contain special characters (e.g., {COURSE_NAME}), but are replaced before compilation.
+    let Ok(text) = placeholders::substitute(text, |_, _| Ok(Some(String::new()))) else {
+        return;
+    };
+
+    let mut math = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); }
+            '$' => math = !math,
+            c if C_LATEX_SPECIAL.contains(&c) || (!math && C_LATEX_SPECIAL_TEXT.contains(&c)) => {
+                errors.push(Issue {
+                    path: path.to_string(),
+                    message: format!("unescaped LaTeX special character '{c}' (write \\{c})")
+                });
+            }
+            _ => {}
+        }
+    }
+
+    if math {
+        errors.push(Issue { path: path.to_string(), message: "math mode ($) is not closed".to_string() });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_command_arguments_of_templates() {
+        let template = "\\section{INTRO} \\ref{FIG1} \\section*{UVOD} \\includegraphics[width=3cm]{LOGO} {CANDIDATE} {UNKNOWN}";
+        let variables = Variables::from([("CANDIDATE".to_string(), String::new())]);
+        let mut report = LintReport::default();
+        check_placeholders(template, "t.tex:1", true, &variables, &mut report);
+        let messages: Vec<&str> = report.warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages, ["unknown placeholder {UNKNOWN} is left unchanged"]);
+
+        // Responses have no commands with placeholder arguments
+        let mut report = LintReport::default();
+        check_placeholders("\\textbf{INTRO}", "$", false, &variables, &mut report);
+        assert_eq!(report.warnings.len(), 1);
+    }
+}
@@ -12,6 +12,7 @@
 //!     cloggen create <CSV DATOTEKA STUDIS ANKET> <JSON NABOR ODZIVOV> <TEX DOKUMENT> -f <FORMAT> -o <IZHODNA POT>
 //! 
 //! - ``<CSV DATOTEKA STUDIS ANKET>`` predstavlja izvoženo CSV datoteko z ocenami kandidata za posamezno vprašanje STUDIS anket
+//!   (namesto CSV datoteke je lahko podana tudi preglednica ``.xlsx``, ``.xls`` ali ``.ods`` z enako vsebino)
 //! - ``<JSON NABOR ODZIVOV>`` predstavlja JSON datoteko, ki definira odgovore za posamezno mejo ocene v formatu:
 //!     ```json
 //!         {
@@ -27,29 +28,260 @@
 //!     }
 //!     ```
 //! 
-//!     Odzivi so razporejeni po večih številkah. Številke so minimalna meja povprečne ocene pri posameznem vprašanju, ki
-//!     jo mora kandidat imeti, zato da dobi enega izmed pripadajočih odzivov.
-//!     
-//!     Odziv bo izbran iz možnih odzivov, ki pripadajo prvi manjši oceni od povprečne ocene kandidata. Na primer, če ima
-//!     kandidat pri vprašanju *Gledano v celoti, je delo izvajalca/ke kakovostno.* povprečno oceno 4.3, bo ob uporabi
-//!     zgornjega JSON primera odziv izbran iz odzivov, ki pripadajo oceni 4.0 (``"4": ["Odziv 1", "Odziv 2", ...]``)
+//!   Odzivi so razporejeni po večih številkah. Številke so minimalna meja povprečne ocene pri posameznem vprašanju, ki
+//!   jo mora kandidat imeti, zato da dobi enega izmed pripadajočih odzivov.
+//!   
+//!   Odziv bo izbran iz možnih odzivov, ki pripadajo prvi manjši oceni od povprečne ocene kandidata. Na primer, če ima
+//!   kandidat pri vprašanju *Gledano v celoti, je delo izvajalca/ke kakovostno.* povprečno oceno 4.3, bo ob uporabi
+//!   zgornjega JSON primera odziv izbran iz odzivov, ki pripadajo oceni 4.0 (``"4": ["Odziv 1", "Odziv 2", ...]``)
 //! 
-//!     V odziv se lahko dinamično vključi tudi **povprečje** in **standardni odklon**, kot prikazuje zgornjni JSON primer:
-//!     ``"4.5": ["Kandidat ima super ocene (povprečje {MEAN} $\\pm$ {STD}).", ...]``. Tu bo ``{MEAN}`` z povprečno oceno za 
-//!     pripadajoče vprašanje, ``{STD}`` pa s standardnim odklonom za pripadajoče vprašanje.
+//!   V odziv se lahko dinamično vključi tudi **povprečje** in **standardni odklon**, kot prikazuje zgornjni JSON primer:
+//!   ``"4.5": ["Kandidat ima super ocene (povprečje {MEAN} $\\pm$ {STD}).", ...]``. Tu bo ``{MEAN}`` z povprečno oceno za 
+//!   pripadajoče vprašanje, ``{STD}`` pa s standardnim odklonom za pripadajoče vprašanje.
 //! 
 //! - ``<TEX DOKUMENT>`` predstavlja glavni LaTeX dokument (datoteko),
-//!     ki bo uporabljen za generacijo izhodnega mnenja v PDF obliki.
-//!     Dokument mora vsebovati ``{AUTO_GEN}`` tekst, ki predstavlja lokacijo
-//!     vstavitve odzivov/odgovorov, generiranih iz zgornje JSON datoteke odzivov.
+//!   ki bo uporabljen za generacijo izhodnega mnenja v PDF obliki.
+//!   Dokument mora vsebovati ``{AUTO_GEN}`` tekst, ki predstavlja lokacijo
+//!   vstavitve odzivov/odgovorov, generiranih iz zgornje JSON datoteke odzivov.
 //! 
 //! - ``<FORMAT>`` predstavlja izhodni format. Privzeta vrednost je ``pdf`` (izhod bo .pdf datoteka),
-//!     lahko pa se izbere tudi ``latex`` (izhod bo .tex latex datoteka).
+//!   lahko pa se izbere tudi ``latex`` (izhod bo .tex latex datoteka), ``markdown``, ``html``, ``txt`` ali ``docx``
+//!   (glej [Izhodni formati](#izhodni-formati)).
 //! - ``<IZHODNA POT>`` predstavlja pot, kamor bo shranjen generiran dokument.
-//!     Privzeto je ta vrednost enaka ``output_<TEX DOKUMENT>.<tex/pdf>``.
+//!   Privzeto je ta vrednost enaka ``output_<TEX DOKUMENT>.<končnica formata>``.
+//! 
+//! ### Izhodni formati
+//! Poleg LaTeX (``latex``) in PDF (``pdf``) dokumentov je mogoče ustvariti tudi Markdown (``markdown`` ali ``md``),
+//! HTML (``html``), navadno besedilo (``txt``) in Word dokumente (``docx``). Predloga je v tem primeru datoteka izbranega
+//! formata (npr. ``mnenje.html``), ki prav tako vsebuje oznako ``{AUTO_GEN}``:
+//! 
+//!     cloggen create anketa.csv odzivi.json mnenje.html -f html
+//! 
+//! Pogosta LaTeX oblikovanja v odzivih so pretvorjena v oblikovanja izbranega formata: ``\textbf{...}`` (krepko),
+//! ``\textit{...}`` in ``\emph{...}`` (ležeče), matematični simboli (npr. ``$\pm$`` v ±), ubežni znaki (npr. ``\%``),
+//! pomišljaji (``--``), narekovaji, prelomi vrstic (``\\``) in odstavkov (prazna vrstica) ter seznami (``itemize`` in
+//! ``enumerate``). Drugi ukazi so odstranjeni, vsebina njihovih argumentov pa ohranjena. Odzivi so v Markdown in HTML
+//! dokumentu ločeni kot odstavki, pri navadnem besedilu pa s presledkom (oblikovanja so odstranjena).
+//! Seme je v Markdown in HTML dokumentu zapisano kot komentar ``<!-- cloggen seed: <SEME> -->``, v navadnem besedilu
+//! pa ni zapisano (je le izpisano).
+//! 
+//! DOCX predloga je Word dokument, ki oznako ``{AUTO_GEN}`` vsebuje v besedilu dokumenta. Odstavek z oznako je zamenjan
+//! z odstavkom odzivov, ki ohrani oblikovanje odstavka in njegovega prvega dela besedila (pisavo, velikost ...), krepko
+//! in ležeče oblikovanje odzivov pa je pretvorjeno v Wordovo. Spremenljivke (npr. ``{CANDIDATE}``) so lahko kjerkoli
+//! v besedilu dokumenta. Dokument je ustvarjen brez zunanjih programov:
+//! 
+//!     cloggen create anketa.csv odzivi.json mnenje.docx -f docx
+//! 
+//! ### Pravila odzivov
+//! Namesto meje povprečne ocene je lahko skupina odzivov podana tudi s pravilom, ki omeji povprečje (``mean``), standardni
+//! odklon (``std``) in število odgovorov (``count``). Vsaka omejitev ima neobvezno spodnjo (``min``, vključno) in zgornjo
+//! (``max``, izključno) mejo. Ime skupine je poljubno:
+//! 
+//! ```json
+//! {
+//!     "Vprašanje": {
+//!         "Gledano v celoti, je delo izvajalca/ke kakovostno.": {
+//!             "malo odgovorov": {
+//!                 "rule": {"count": {"max": 5}},
+//!                 "responses": ["Na anketo je odgovorilo le {COUNT} študentov, zato ocena ni zanesljiva."]
+//!             },
+//!             "enotno odlično": {
+//!                 "rule": {"mean": {"min": 4.5}, "std": {"max": 0.5}},
+//!                 "responses": ["Študenti so delo enotno ocenili kot odlično.", "Odziv 2", ...]
+//!             },
+//!             "4": ["Odziv 1", "Odziv 2", ...],
+//!             "1": ["Odziv 1", "Odziv 2", ...]
+//!         }
+//!     }
+//! }
+//! ```
+//! 
+//! Pravila so preverjena v vrstnem redu datoteke, pred mejami povprečne ocene. Uporabi se prva skupina, ki ustreza ocenam
+//! vprašanja. Pravilo z omejitvijo standardnega odklona ali števila odgovorov ne ustreza, če tega podatka v STUDIS
+//! datoteki ni. Meje povprečne ocene in pravila, ki omejijo le povprečje, morajo skupaj pokriti vsa povprečja od 1 do 5,
+//! sicer je datoteka odzivov zavrnjena.
+//! 
+//! ### Ujemanje vprašanj
+//! STUDIS občasno spremeni ločila, zapis spola (npr. *izvajalca/ke*) ali presledke v besedilu vprašanj. Vprašanja iz JSON
+//! datoteke so zato z vprašanji CSV datoteke povezana po vrsti:
+//! 
+//! 1. po enakem besedilu,
+//! 2. po enem izmed drugih besedil vprašanja, naštetih pod ključem ``"aliases"``,
+//! 3. po normaliziranem besedilu (Unicode normalizacija, male črke, brez ločil in odvečnih presledkov),
+//! 4. po najbolj podobnem besedilu, če je podobnost (med 0 in 1) vsaj ``--similarity`` (privzeto 0.9).
+//!    Z ``--similarity 1`` je podobnostno ujemanje izklopljeno.
+//! 
+//! ```json
+//! {
+//!     "Vprašanje": {
+//!         "Gledano v celoti, je delo izvajalca/ke kakovostno.": {
+//!             "aliases": ["Gledano v celoti je delo izvajalca kakovostno."],
+//!             "4": ["Odziv 1", "Odziv 2", ...]
+//!         }
+//!     }
+//! }
+//! ```
+//! 
+//! Ukaz ``create`` izpiše vsa vprašanja, ki niso bila povezana po enakem besedilu, ukaz ``lint`` pa jih izpiše kot
+//! opozorila. Vprašanja, ki jih ni mogoče povezati, so izpisana skupaj z najbolj podobnim vprašanjem CSV datoteke.
+//! Enako ujemanje uporablja tudi ukaz ``merge`` za vprašanja različnih datotek.
+//! 
+//! ### Oznake in spremenljivke
+//! Odzivi lahko vsebujejo naslednje oznake, ki se zamenjajo z vrednostmi pripadajočega vprašanja:
+//! 
+//! - ``{MEAN}`` - povprečna ocena z enakim številom decimalk kot v STUDIS izvozu, ``{MEAN:1}`` pa povprečna ocena,
+//!   zaokrožena na 1 decimalko (podobno ``{MEAN:2}`` ...),
+//! - ``{STD}`` - standardni odklon, ``{STD:1}`` pa standardni odklon, zaokrožen na 1 decimalko,
+//! - ``{COUNT}`` - število odgovorov (stolpec ``Število odgovorov``),
+//! - ``{QUESTION}`` - besedilo vprašanja,
+//! - ``{SECTION}`` - ime sekcije ankete.
+//! 
+//! Poleg tega je mogoče z opcijo ``--var <IME>=<VREDNOST>`` (lahko večkrat) podati lastne spremenljivke, ki zamenjajo
+//! oznako ``{IME}`` tako v odzivih kot v LaTeX predlogi. Imena spremenljivk so sestavljena iz velikih črk, številk in
+//! podčrtajev:
+//! 
+//!     cloggen create anketa.csv odzivi.json mnenje.tex --var CANDIDATE="Janez Novak" --var COURSE="Osnove elektrotehnike" --var DATE=1.10.2025
+//! 
+//! V predlogi se zamenja tudi oznaka ``{SECTION}`` (s privzeto sekcijo). Oznake z neznanim imenom ostanejo nespremenjene.
+//! 
+//! Vrednosti oznak se ubežijo glede na izhodni format, zato posebni znaki (npr. ``&``, ``%``, ``_`` v imenu
+//! ``O'Brien & Co``) ne pokvarijo prevajanja. V LaTeX (tudi v odzivih, ki so vedno v LaTeX-u) se znaki ubežijo
+//! v besedilnem ali matematičnem načinu, odvisno od mesta oznake, v HTML in Markdown pa po pravilih teh jezikov.
+//! Če je vrednost že zapisana v LaTeX-u (npr. ``--var COURSE='\textbf{OE}'``), se ubežanje izklopi z argumentom ``raw``:
+//! ``{COURSE:raw}`` oziroma ``{MEAN:1,raw}``. Besedilo, ki ga zapišete v odzive in predloge, se nikoli ne spreminja.
+//! 
+//! ### Več sekcij ankete
+//! STUDIS izvoz vsebuje več sekcij (npr. *Anketa o izvajalcu*, *Anketa o predmetu* in
+//! *Anketa o predmetu po zaključnem preverjanju znanja*). Oznaka ``{AUTO_GEN}`` v predlogi se napolni z odzivi privzete
+//! sekcije (opcija ``--section``, privzeto *Anketa o izvajalcu*), oznaka ``{AUTO_GEN:<SEKCIJA>}`` pa z odzivi podane
+//! sekcije. Odzivi za druge sekcije so v JSON datoteki zapisani pod imenom sekcije:
+//! 
+//! ```json
+//! {
+//!     "Vprašanje": { ... },
+//!     "Anketa o predmetu": {
+//!         "Vprašanje": {
+//!             "Gledano v celoti, sem s predmetom zadovoljen/a.": {
+//!                 "4": ["Odziv 1", "Odziv 2", ...]
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//! 
+//! Vprašanja na najvišjem nivoju (``"Vprašanje"``) pripadajo privzeti sekciji.
+//! 
+//! ### Ponovljivost
+//! Odzivi so izbrani naključno. Ukaz ``create`` ob koncu izpiše uporabljeno seme (``seed``), ki je zapisano tudi v
+//! generiran dokument (LaTeX komentar ``% cloggen seed: ...`` in polje ``CloggenSeed`` v lastnostih PDF oz. DOCX dokumenta).
+//! Z opcijo ``--seed <SEME>`` se ob enakih vhodnih datotekah ustvari enak dokument:
+//! 
+//!     cloggen create anketa.csv odzivi.json mnenje.tex --seed 1234
+//! 
+//! ### Raznolikost odzivov
+//! Pri generiranju mnenj za več kandidatov naključna izbira pogosto več kandidatom dodeli enake stavke. Z opcijo
+//! ``--diversity <DATOTEKA STANJA>`` (pri ukazih ``create`` in ``batch``) Cloggen v podano JSON datoteko beleži, kolikokrat
+//! je bil uporabljen posamezni odziv, in izmed odzivov pripadajoče meje ocene izbere enega izmed najmanjkrat uporabljenih:
+//! 
+//!     cloggen batch kandidati.toml --diversity seja.json
+//! 
+//! Dokler se uporablja ista datoteka stanja, se mnenja med seboj čim bolj razlikujejo. Za novo sejo datoteko izbriši.
+//! Ob uporabi datoteke stanja je izbira odvisna tudi od njene vsebine, zato isto seme ne ustvari nujno enakega dokumenta.
+//! 
+//! ### Napake pri prevajanju
+//! Če prevajanje PDF dokumenta ne uspe (npr. zaradi neubežnega znaka ``%`` ali ``&`` v odzivu), so izpisane napake iz
+//! LaTeX dnevnika, skupaj z vrstico dokumenta in odzivom (oz. vrstico predloge), iz katerega vrstica izvira:
+//! 
+//!     error: LaTeX compilation failed:
+//!       line 5: Misplaced alignment tab character &. (after "Ocena je 50 &")
+//!         in the response to "Snov podaja na razumljiv način." [Anketa o izvajalcu]: "Ocena je 50 & dobro."
+//! 
+//! Grafični vmesnik ob napaki prikaže tudi celoten LaTeX dnevnik (*Podrobnosti*).
+//! 
+//! ## Preverjanje odzivov
+//! Neujemanja med JSON datoteko odzivov in STUDIS CSV datoteko se pri ukazu ``create`` pokažejo posamično. Ukaz ``lint``
+//! preveri JSON datoteko odzivov (ter neobvezno STUDIS CSV datoteko in LaTeX predlogo) in izpiše vse najdene težave naenkrat:
+//! 
+//!     cloggen lint <JSON NABOR ODZIVOV> -c <CSV DATOTEKA STUDIS ANKET> -t <TEX DOKUMENT> --var CANDIDATE
+//! 
+//! Preverjeno je:
+//! 
+//! - ali je JSON datoteka v pravilni obliki (številske meje ocen, neprazni seznami odzivov, pravila),
+//! - ali so vsa vprašanja in sekcije iz JSON datoteke prisotni v STUDIS CSV datoteki (vprašanja CSV datoteke brez
+//!   odzivov so izpisana kot opozorilo),
+//! - ali so oznake (npr. ``{MEAN:1}``) v odzivih in predlogi znane (spremenljivke, podane z ``--var``, so znane,
+//!   argumenti LaTeX ukazov predloge, npr. ``\section{UVOD}``, pa niso oznake),
+//! - ali so posebni LaTeX znaki (``%``, ``&``, ``#``, ``_``, ``^`` in ``$``) v odzivih pravilno zapisani.
+//! 
+//! Ukaz se zaključi z napako, če je najdena vsaj ena napaka (opozorila ne štejejo).
+//! 
+//! ## Generiranje za več kandidatov
+//! Ukaz ``batch`` generira mnenja za vse kandidate, navedene v datoteki z navodili (manifest):
+//! 
+//!     cloggen batch <MANIFEST> -r <JSON NABOR ODZIVOV> -t <TEX DOKUMENT> -f <FORMAT>
+//! 
+//! Manifest je TOML datoteka:
+//! 
+//! ```toml
+//! # Privzete vrednosti za vse kandidate (neobvezno)
+//! responses = "odzivi.json"
+//! template = "mnenje.tex"
+//! 
+//! [[candidate]]
+//! name = "Janez Novak"
+//! csv = ["novak_2023.csv", "novak_2024.csv"]
+//! output = "mnenja/novak"
+//! seed = 1234  # neobvezno
+//! vars = { COURSE = "Osnove elektrotehnike" }  # neobvezno
+//! ```
+//! 
+//! ali CSV datoteka s stolpci ``name``, ``csv``, ``output`` in neobveznimi ``responses``, ``template`` ter ``seed``.
+//! Več CSV datotek kandidata je v CSV manifestu ločenih z ``|``:
+//! 
+//! ```text
+//! name;csv;output
+//! Janez Novak;novak_2023.csv|novak_2024.csv;mnenja/novak
+//! ```
+//! 
+//! Relativne poti v manifestu so relativne glede na mapo manifesta. Odzivi in predloga, ki niso podani pri kandidatu, se
+//! vzamejo iz manifesta oziroma iz opcij ``-r`` in ``-t``. Kadar ima kandidat več STUDIS CSV datotek, se te pred
+//! generiranjem združijo (kot z ukazom ``merge``, z opcijama ``-w`` in ``--std-mode``).
+//! 
+//! Napaka pri enem kandidatu ne prekine generiranja ostalih. Na koncu je izpisana tabela uspešno generiranih mnenj in
+//! napak.
+//! 
+//! Spremenljivka ``{CANDIDATE}`` (glej *Oznake in spremenljivke*) ima privzeto vrednost imena kandidata. Spremenljivke
+//! vseh kandidatov se podajo z opcijo ``--var``, spremenljivke posameznega kandidata pa s ključem ``vars`` v TOML manifestu.
+//! 
+//! Prevajanje PDF dokumentov traja nekaj sekund na dokument. Z opcijo ``-j`` (``--jobs``) se več dokumentov prevaja
+//! hkrati, vsak v ločenem procesu:
+//! 
+//!     cloggen batch kandidati.toml -j 4
+//! 
+//! Pri tem se najprej generirajo vsa mnenja, nato pa se prevedejo. Prvi dokument se prevede sam, da se pripravi predpomnilnik
+//! formata (in prenesejo datoteke paketa), ki ga nato uporabijo ostali. Za vsak dokument se sproti izpiše napredek
+//! (npr. ``[3/20]``) oziroma napaka.
+//! 
+//! ## Prevajanje brez povezave
+//! LaTeX prevajalnik ob prvi uporabi prenese potrebne TeX pakete (in pisave) iz spleta ter jih shrani v predpomnilnik.
+//! Na računalniku brez povezave je zato mogoče uporabiti:
+//! 
+//! - ``--offline`` - uporabijo se le že preneseni paketi iz predpomnilnika,
+//! - ``--bundle <PAKET>`` - uporabijo se paketi lokalnega TeX paketa: mape, ``.zip`` ali ``.tar`` datoteke.
+//! 
+//! Obe opciji sta na voljo pri ukazih ``create``, ``batch`` in ``compile``. Lokalni paket se ustvari na računalniku s
+//! povezavo, z ukazom ``bundle``, ki prevede podane LaTeX datoteke (npr. predloge mnenj, brez oznak) in izvozi vse
+//! uporabljene datoteke. Odstranijo se vgrajene oznake (npr. ``{AUTO_GEN}``) in spremenljivke, podane z opcijo
+//! ``--var <IME>``:
+//! 
+//!     cloggen bundle mnenje.tex -o paket.zip
+//!     cloggen create anketa.csv odzivi.json mnenje.tex --bundle paket.zip
+//! 
+//! Izvozi se paket ``.zip`` ali ``.tar`` (glede na končnico), sicer pa mapa. Če paketu manjka datoteka (npr. paket, ki ga
+//! uporablja le odziv), je v napaki izpisano njeno ime. Lokalni paket je mogoče izbrati tudi v grafičnem vmesniku.
 //! 
 //! ## Združevanje STUDIS anket
-//! Cloggen omogoča združevanje večih STUDIS CSV datotek v eno skupno datoteko.
+//! Cloggen omogoča združevanje večih STUDIS CSV datotek (ali preglednic) v eno skupno datoteko.
 //! Združijo se le povprečne ocene posameznih datotekek, tako, da se povprečijo.
 //! Standardni odklon je na novo izračunan iz povprečij datotek.
 //! 
@@ -61,22 +293,48 @@
 //!     cloggen merge <csv1> <csv2> ...
 //! 
 //! Izhodna pot združene datoteke je privzeto ``./merged.csv``. Za lastno pot uporabi ``-o <IZHODNA POT>``
+//! 
+//! ### Uteževanje
+//! Privzeto vsaka datoteka prispeva enako k združenemu povprečju (``-w equal``). Z ``-w respondents`` je povprečje
+//! vsake datoteke uteženo s številom odgovorov (stolpec ``Število odgovorov``), tako da predmet s 120 odgovori
+//! prispeva več kot predmet s 3 odgovori. Združena datoteka vsebuje tudi skupno število odgovorov.
+//! 
+//!     cloggen merge <csv1> <csv2> ... -w respondents
+//! 
+//! ### Standardni odklon
+//! Privzeto je standardni odklon združene datoteke izračunan iz povprečij datotek (``--std-mode means``), zato ne
+//! upošteva odklonov posameznih datotek. Z ``--std-mode pooled`` je izračunan pravi skupni (vzorčni) standardni odklon vseh
+//! odgovorov, ki združi povprečja, standardne odklone in števila odgovorov posameznih datotek. Ta način je smiselno
+//! uporabiti skupaj z ``-w respondents``, saj je odklon izračunan okoli povprečja, uteženega s številom odgovorov.
+//! 
+//!     cloggen merge <csv1> <csv2> ... -w respondents --std-mode pooled
+//! 
+//! ## Pregled STUDIS izvoza
+//! Cloggen kodiranje (encoding) in ločilo CSV datoteke zazna samodejno. Med možnimi kodiranji (npr. UTF-8, UTF-16,
+//! Windows-1250 in ISO-8859-2) izbere tisto, pri katerem je besedilo najbolj smiselno (vsebuje črke č, š, ž in imena
+//! stolpcev STUDIS izvoza). Kadar izvoz ni prebran pravilno (npr. po shranjevanju v drugem programu), ukaz ``inspect``
+//! izpiše zaznano kodiranje, ločilo in decimalno ločilo posamezne sekcije ter vsa prebrana vprašanja z ocenami:
+//! 
+//!     cloggen inspect <CSV DATOTEKA STUDIS ANKET>
+//! 
+//! Kodiranje in ločilo je mogoče podati tudi ročno, z ``--encoding`` (npr. ``windows-1250``, ``utf-8``) in
+//! ``--delimiter`` (en znak, npr. ``;``, ali ``tab``). Opciji sprejmejo tudi ukazi ``create``, ``batch``, ``lint`` in
+//! ``merge``:
+//! 
+//!     cloggen create anketa.csv odzivi.json mnenje.tex --encoding windows-1250 --delimiter tab
 
 
 use clap::{Parser, Subcommand};
+use encoding_rs::Encoding;
 use std::path::PathBuf;
+use std::process::ExitCode;
+
+use cloggen::{batch, compiler, config, create, fs, lint, merge, placeholders, preproc};
+use cloggen::matching::{self, MatchKind};
 
 #[cfg(feature = "gui")]
 mod gui;
 
-mod compiler;
-mod preproc;
-mod config;
-mod macros;
-mod create;
-mod merge;
-mod fs;
-
 
 #[derive(Parser)]
 #[command(version)]
@@ -100,7 +358,8 @@ enum Commands {
         /// Path to the output LaTeX file.
         tex_template_filepath: PathBuf,
 
-        /// Name of the grades section to use (e.g., "Anketa o izvajalcu")
+        /// Name of the default grades section (e.g., "Anketa o izvajalcu"), used for the {AUTO_GEN}
+        /// placeholder. Other sections are filled through {AUTO_GEN:<section>} placeholders.
         #[clap(short, long, default_value = config::create::SECTION_DEFAULT)]
         section: String,
 
@@ -110,13 +369,195 @@ enum Commands {
 
         /// Path of the output file.
         #[clap(short)]
-        output_filepath: Option<PathBuf>
+        output_filepath: Option<PathBuf>,
+
+        /// Seed of the response selection. Reusing the seed of a previous run with the
+        /// same inputs reproduces the same document. Random by default.
+        #[clap(long)]
+        seed: Option<u64>,
+
+        /// User variable, given as NAME=value (e.g., CANDIDATE="Janez Novak"). It replaces
+        /// the {NAME} placeholder in the responses and the template. Can be given multiple times.
+        #[clap(long = "var", value_name = "NAME=VALUE", value_parser = placeholders::parse_variable)]
+        variables: Vec<(String, String)>,
+
+        /// Minimum similarity (0 to 1) of a question's text in the responses file and the CSV file,
+        /// when the texts differ (e.g., in punctuation). Use 1 to disable fuzzy matching.
+        #[clap(long, default_value = config::create::SIMILARITY_DEFAULT_STR, value_parser = matching::parse_similarity)]
+        similarity: f64,
+
+        /// Encoding of the STUDIS CSV file (e.g., windows-1250). Detected by default.
+        #[clap(long, value_parser = fs::parse_encoding)]
+        encoding: Option<&'static Encoding>,
+
+        /// Delimiter of the STUDIS CSV file (a single character, tab or space). Detected by default.
+        #[clap(long, value_parser = preproc::parse_delimiter)]
+        delimiter: Option<char>,
+
+        /// State file of the used responses. When given, responses that were not used yet (by previous
+        /// reports with the same state file) are preferred, so that reports differ as much as possible.
+        #[clap(long = "diversity", value_name = "STATE_FILE")]
+        usage_filepath: Option<PathBuf>,
+
+        /// Local bundle of TeX support files (a directory, .zip or .tar file), e.g., exported
+        /// with the bundle command. By default, the files are downloaded on first use.
+        #[clap(long)]
+        bundle: Option<PathBuf>,
+
+        /// Use only the already downloaded TeX support files, without network access.
+        #[clap(long)]
+        offline: bool
+    },
+
+    /// Generate habilitation reports of multiple candidates, listed in a manifest
+    Batch {
+        /// The manifest (.toml or .csv) listing the candidates' names, STUDIS CSV files,
+        /// templates and output paths.
+        manifest: PathBuf,
+
+        /// JSON file of the possible responses, for candidates that don't define their own.
+        #[clap(short, long)]
+        responses: Option<PathBuf>,
+
+        /// LaTeX template, for candidates that don't define their own.
+        #[clap(short, long)]
+        template: Option<PathBuf>,
+
+        /// Name of the default grades section (e.g., "Anketa o izvajalcu"), used for the {AUTO_GEN} placeholder.
+        #[clap(short, long, default_value = config::create::SECTION_DEFAULT)]
+        section: String,
+
+        /// The format of output files.
+        #[clap(short, long, default_value = config::create::FORMAT_DEFAULT_STR)]
+        format: create::OutputFormat,
+
+        /// How the means are weighted, when a candidate has multiple CSV files.
+        #[clap(short, long, default_value = config::merge::WEIGHTING_DEFAULT_STR)]
+        weighting: merge::MergeWeighting,
+
+        /// How the standard deviation is calculated, when a candidate has multiple CSV files.
+        #[clap(long, default_value = config::merge::STD_MODE_DEFAULT_STR)]
+        std_mode: merge::StdMode,
+
+        /// User variable of all candidates, given as NAME=value. Variables of the manifest take precedence.
+        /// Can be given multiple times.
+        #[clap(long = "var", value_name = "NAME=VALUE", value_parser = placeholders::parse_variable)]
+        variables: Vec<(String, String)>,
+
+        /// Minimum similarity (0 to 1) of a question's text in the responses file and the CSV file,
+        /// when the texts differ (e.g., in punctuation). Use 1 to disable fuzzy matching.
+        #[clap(long, default_value = config::create::SIMILARITY_DEFAULT_STR, value_parser = matching::parse_similarity)]
+        similarity: f64,
+
+        /// Encoding of the STUDIS CSV files (e.g., windows-1250). Detected by default.
+        #[clap(long, value_parser = fs::parse_encoding)]
+        encoding: Option<&'static Encoding>,
+
+        /// Delimiter of the STUDIS CSV files (a single character, tab or space). Detected by default.
+        #[clap(long, value_parser = preproc::parse_delimiter)]
+        delimiter: Option<char>,
+
+        /// State file of the used responses. When given, responses that were not used yet (by previous
+        /// reports with the same state file) are preferred, so that reports differ as much as possible.
+        #[clap(long = "diversity", value_name = "STATE_FILE")]
+        usage_filepath: Option<PathBuf>,
+
+        /// Local bundle of TeX support files (a directory, .zip or .tar file), e.g., exported
+        /// with the bundle command. By default, the files are downloaded on first use.
+        #[clap(long)]
+        bundle: Option<PathBuf>,
+
+        /// Use only the already downloaded TeX support files, without network access.
+        #[clap(long)]
+        offline: bool,
+
+        /// Number of PDF reports compiled concurrently (each by a separate process).
+        #[clap(short, long, default_value = config::batch::JOBS_DEFAULT_STR)]
+        jobs: usize
+    },
+
+    /// Check a JSON responses file (and optionally its match with a STUDIS CSV file and a template)
+    Lint {
+        /// JSON file of the possible responses per category per grade.
+        response_json_filepath: PathBuf,
+
+        /// CSV file of the STUDIS grades, whose questions should match the responses.
+        #[clap(short, long)]
+        csv: Option<PathBuf>,
+
+        /// LaTeX template, whose placeholders should match the responses.
+        #[clap(short, long)]
+        template: Option<PathBuf>,
+
+        /// Name of the default grades section (e.g., "Anketa o izvajalcu"), used for the {AUTO_GEN} placeholder.
+        #[clap(short, long, default_value = config::create::SECTION_DEFAULT)]
+        section: String,
+
+        /// Name of a user variable (NAME or NAME=value), which will be given when generating the report.
+        /// Can be given multiple times.
+        #[clap(long = "var", value_name = "NAME", value_parser = lint::parse_variable_name)]
+        variables: Vec<(String, String)>,
+
+        /// Minimum similarity (0 to 1) of a question's text in the responses file and the CSV file,
+        /// when the texts differ (e.g., in punctuation). Use 1 to disable fuzzy matching.
+        #[clap(long, default_value = config::create::SIMILARITY_DEFAULT_STR, value_parser = matching::parse_similarity)]
+        similarity: f64,
+
+        /// Encoding of the STUDIS CSV file (e.g., windows-1250). Detected by default.
+        #[clap(long, value_parser = fs::parse_encoding)]
+        encoding: Option<&'static Encoding>,
+
+        /// Delimiter of the STUDIS CSV file (a single character, tab or space). Detected by default.
+        #[clap(long, value_parser = preproc::parse_delimiter)]
+        delimiter: Option<char>
     },
 
     /// Access to the underlaying LaTeX compiler. Use this when you want to directly
     /// compile a file. If you wish to create a habilitation report, use the [`create`] command.
     Compile {
-        tex_file: PathBuf
+        tex_file: PathBuf,
+
+        /// Local bundle of TeX support files (a directory, .zip or .tar file), e.g., exported
+        /// with the bundle command. By default, the files are downloaded on first use.
+        #[clap(long)]
+        bundle: Option<PathBuf>,
+
+        /// Use only the already downloaded TeX support files, without network access.
+        #[clap(long)]
+        offline: bool
+    },
+
+    /// Download the TeX support files (packages, fonts, ...) used by LaTeX files (e.g., the templates)
+    /// and export them as a bundle for offline compilation (see the --bundle option)
+    Bundle {
+        /// The LaTeX files whose support files are exported. Their placeholders are removed before compilation.
+        #[clap(num_args = 1.., required = true)]
+        tex_files: Vec<PathBuf>,
+
+        /// Name of a user variable (NAME or NAME=value) used by the files, whose placeholder is removed as well.
+        /// Can be given multiple times.
+        #[clap(long = "var", value_name = "NAME", value_parser = lint::parse_variable_name)]
+        variables: Vec<(String, String)>,
+
+        /// Path of the exported bundle: a .zip file, a .tar file or (with any other extension) a directory.
+        #[clap(short, long, default_value = config::bundle::OUTPUT_DEFAULT)]
+        output: PathBuf
+    },
+
+    /// Compile a LaTeX file for a concurrent batch compilation (used internally by the batch command)
+    #[clap(name = compiler::WORKER_COMMAND, hide = true)]
+    CompileWorker {
+        tex_file: PathBuf,
+
+        /// The directory of the files referenced by the LaTeX file.
+        #[clap(long)]
+        root: PathBuf,
+
+        #[clap(long)]
+        bundle: Option<PathBuf>,
+
+        #[clap(long)]
+        offline: bool
     },
 
     /// Merges grades of multiple CSV files
@@ -133,15 +574,50 @@ enum Commands {
 
         /// Path of the output (merged) file.
         #[clap(short, long, default_value = config::merge::OUTPUT_DEFAULT)]
-        output: PathBuf
+        output: PathBuf,
+
+        /// How the means of different files are weighted.
+        #[clap(short, long, default_value = config::merge::WEIGHTING_DEFAULT_STR)]
+        weighting: merge::MergeWeighting,
+
+        /// How the merged standard deviation is calculated.
+        #[clap(long, default_value = config::merge::STD_MODE_DEFAULT_STR)]
+        std_mode: merge::StdMode,
+
+        /// Minimum similarity (0 to 1) of a question's text in different files, when the texts differ
+        /// (e.g., in punctuation). Use 1 to disable fuzzy matching.
+        #[clap(long, default_value = config::merge::SIMILARITY_DEFAULT_STR, value_parser = matching::parse_similarity)]
+        similarity: f64,
+
+        /// Encoding of the STUDIS CSV files (e.g., windows-1250). Detected by default.
+        #[clap(long, value_parser = fs::parse_encoding)]
+        encoding: Option<&'static Encoding>,
+
+        /// Delimiter of the STUDIS CSV files (a single character, tab or space). Detected by default.
+        #[clap(long, value_parser = preproc::parse_delimiter)]
+        delimiter: Option<char>
+    },
+
+    /// Show how a STUDIS export is read: its encoding, delimiter, sections, questions and grades
+    Inspect {
+        /// The STUDIS export (CSV file or spreadsheet).
+        file: PathBuf,
+
+        /// Encoding of the CSV file (e.g., windows-1250). Detected by default.
+        #[clap(long, value_parser = fs::parse_encoding)]
+        encoding: Option<&'static Encoding>,
+
+        /// Delimiter of the CSV file (a single character, tab or space). Detected by default.
+        #[clap(long, value_parser = preproc::parse_delimiter)]
+        delimiter: Option<char>
     }
 }
 
 
-fn main() {
+fn main() -> ExitCode {
     match Args::try_parse() {  // Terminal mode
         Ok(cli) => {
-            match &cli.command {
+            let result = match cli.command {
                 Commands::Create {
                     studis_csv_filepath,
                     response_json_filepath,
@@ -149,24 +625,128 @@ fn main() {
                     section,
                     format,
                     output_filepath,
+                    seed,
+                    variables,
+                    similarity,
+                    encoding,
+                    delimiter,
+                    usage_filepath,
+                    bundle,
+                    offline
                 } => {
                     create::command_create(
-                        studis_csv_filepath,
-                        response_json_filepath,
-                        tex_template_filepath,
-                        section,
-                        format,
-                        output_filepath
-                    ).unwrap();
+                        &studis_csv_filepath,
+                        &response_json_filepath,
+                        &tex_template_filepath,
+                        &create::CreateOptions {
+                            section, format, output_filepath, seed, similarity, usage_filepath,
+                            variables: variables.into_iter().collect(),
+                            survey: preproc::SurveyOptions { encoding, delimiter, ..Default::default() },
+                            compile: compiler::CompileOptions { bundle, offline }
+                        }
+                    ).map(|report| {
+                        for (section, matched) in report.matches.iter().filter(|(_, m)| m.kind != MatchKind::Exact) {
+                            println!(
+                                "Matched \"{}\" to \"{}\" in {section} ({})",
+                                matched.question, matched.csv_question, matched.kind
+                            );
+                        }
+                        println!("Created {} (seed {})", report.path.display(), report.seed)
+                    })
+                }
+
+                Commands::Batch {
+                    manifest, responses, template, section, format, weighting, std_mode, variables, similarity,
+                    encoding, delimiter, usage_filepath, bundle, offline, jobs
+                } => {
+                    // The workers are processes of this executable (see the hidden compile-worker command)
+                    let workers = std::env::current_exe().ok()
+                        .filter(|_| jobs > 1)
+                        .map(|executable| compiler::Workers { executable, count: jobs });
+                    let options = batch::BatchOptions {
+                        responses, template, section, format, weighting, std_mode, similarity, usage_filepath, workers,
+                        variables: variables.into_iter().collect(),
+                        survey: preproc::SurveyOptions { encoding, delimiter, ..Default::default() },
+                        compile: compiler::CompileOptions { bundle, offline }
+                    };
+                    batch::command_batch(&manifest, &options, |outcome, done, total| match &outcome.result {
+                        Ok(report) => println!("[{done}/{total}] Created {} for {}", report.path.display(), outcome.name),
+                        Err(e) => println!("[{done}/{total}] Failed to create the report for {}: {e}", outcome.name)
+                    }).and_then(|outcomes| {
+                        println!("\n{}", batch::summary_table(&outcomes));
+                        match outcomes.iter().filter(|o| o.result.is_err()).count() {
+                            0 => Ok(()),
+                            n => Err(cloggen::Error::Input(format!("{n} of {} reports failed", outcomes.len())))
+                        }
+                    })
+                }
+
+                Commands::Lint {
+                    response_json_filepath, csv, template, section, variables, similarity, encoding, delimiter
+                } => {
+                    lint::command_lint(
+                        &response_json_filepath,
+                        csv.as_deref(),
+                        template.as_deref(),
+                        &lint::LintOptions {
+                            section, similarity,
+                            variables: variables.into_iter().collect(),
+                            survey: preproc::SurveyOptions { encoding, delimiter, ..Default::default() }
+                        }
+                    ).and_then(|report| {
+                        report.errors.iter().for_each(|issue| println!("error: {issue}"));
+                        report.warnings.iter().for_each(|issue| println!("warning: {issue}"));
+                        println!("{} errors, {} warnings", report.errors.len(), report.warnings.len());
+                        match report.errors.len() {
+                            0 => Ok(()),
+                            n => Err(cloggen::Error::Input(format!("lint found {n} errors")))
+                        }
+                    })
                 }
 
-                Commands::Compile { tex_file } => {
-                    compiler::cmd_compile(tex_file).unwrap();
+                Commands::Compile { tex_file, bundle, offline } => {
+                    compiler::command_compile(&tex_file, &compiler::CompileOptions { bundle, offline }).map(|_| ())
                 }
 
-                Commands::Merge { csv_file_patterns , section, output}  => {
-                    merge::command_merge(csv_file_patterns, section, output).unwrap();
+                Commands::CompileWorker { tex_file, root, bundle, offline } => {
+                    compiler::command_compile_worker(&tex_file, &root, &compiler::CompileOptions { bundle, offline })
                 }
+
+                Commands::Bundle { tex_files, variables, output } => {
+                    compiler::command_bundle(&tex_files, &variables.into_iter().collect(), &output)
+                        .map(|count| println!("Exported {count} files to {}", output.display()))
+                }
+
+                Commands::Merge {
+                    csv_file_patterns , section, output, weighting, std_mode, similarity, encoding, delimiter
+                }  => {
+                    merge::command_merge(
+                        &csv_file_patterns,
+                        &output,
+                        &merge::MergeOptions {
+                            section, weighting, std_mode, similarity,
+                            survey: preproc::SurveyOptions { encoding, delimiter, ..Default::default() }
+                        }
+                    ).map(|matches| {
+                        for matched in matches {
+                            println!(
+                                "Merged \"{}\" of {} with \"{}\" ({})",
+                                matched.question, matched.file.display(), matched.merged_question, matched.kind
+                            );
+                        }
+                    })
+                }
+
+                Commands::Inspect { file, encoding, delimiter } => {
+                    let options = preproc::SurveyOptions { encoding, delimiter, ..Default::default() };
+                    preproc::SurveyExport::inspect(&file, &options)
+                        .and_then(|inspection| print_inspection(inspection, encoding.is_some()))
+                }
+            };
+
+            if let Err(e) = result {
+                print_error(&e);
+                return ExitCode::FAILURE;
             }
         }
         #[cfg(feature = "gui")]
@@ -177,4 +757,53 @@ fn main() {
             println!("{e}");
         }
     }
+
+    ExitCode::SUCCESS
+}
+
+
+/// Prints how a STUDIS export was read (see the ``inspect`` command).
+/// Returns the error of reading the sections, after printing the encoding.
+fn print_inspection(inspection: preproc::Inspection, given_encoding: bool) -> cloggen::Result<()> {
+    match inspection.encoding {
+        Some(encoding) => println!("Encoding: {} ({})", encoding.name(), if given_encoding { "given" } else { "detected" }),
+        None => println!("Encoding: none (spreadsheet)")
+    }
+
+    let sections = inspection.sections?;
+    println!("Sections: {}", sections.len());
+    for (section, layout) in &sections {
+        let location = match &layout.sheet {
+            Some(sheet) => format!("sheet \"{sheet}\", row {}", layout.line),
+            None => format!("line {}", layout.line)
+        };
+        let delimiter = layout.delimiter
+            .map(|d| format!(", delimiter {}", preproc::delimiter_name(d)))
+            .unwrap_or_default();
+        println!(
+            "\n{} ({location}{delimiter}, decimal separator \"{}\", {} questions)",
+            section.title, layout.decimal.char(), section.rows.len()
+        );
+
+        let missing = || "-".to_string();
+        for (i, row) in section.rows.iter().enumerate() {
+            println!(
+                "  {}. {}\n     respondents: {}, mean: {}, std: {}",
+                i + 1, row.question,
+                row.count.map_or_else(missing, |c| c.to_string()), row.mean, row.std.map_or_else(missing, |s| s.to_string())
+            );
+        }
+    }
+    Ok(())
+}
+
+
+/// Prints the ``error`` and the chain of its causes to stderr.
+fn print_error(error: &cloggen::Error) {
+    eprintln!("error: {error}");
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        eprintln!("  caused by: {cause}");
+        source = cause.source();
+    }
 }
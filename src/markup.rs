@@ -0,0 +1,442 @@
+//! Conversion of the LaTeX markup used in responses to other markup languages.
+//!
+//! Only the markup common in responses is converted: text styles (``\textbf``, ``\textit``, ``\emph``),
+//! math symbols (e.g., ``$\pm$``), escaped characters, dashes, quotes, line and paragraph breaks and lists
+//! (``itemize`` and ``enumerate``). Other commands are removed, keeping the content of their arguments.
+
+use std::iter::Peekable;
+use std::mem;
+use std::str::Chars;
+
+
+/// The target markup language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Markup {
+    /// Plain text, without styles.
+    Plain,
+    /// Markdown.
+    Markdown,
+    /// HTML (body content).
+    Html
+}
+
+
+/// A piece of text with a uniform style.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Run {
+    /// The text. Line breaks are represented with ``'\n'``.
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool
+}
+
+
+/// A text style.
+#[derive(Clone, Copy)]
+enum Style {
+    Bold,
+    Italic
+}
+
+
+/// Commands replaced by a symbol (in or outside math mode).
+const SYMBOLS: [(&str, &str); 21] = [
+    ("pm", "±"), ("mp", "∓"), ("cdot", "·"), ("times", "×"), ("div", "÷"),
+    ("leq", "≤"), ("le", "≤"), ("geq", "≥"), ("ge", "≥"), ("neq", "≠"),
+    ("approx", "≈"), ("ldots", "…"), ("dots", "…"), ("textbackslash", "\\"), ("backslash", "\\"),
+    ("textless", "<"), ("textgreater", ">"), ("textasciitilde", "~"), ("sim", "~"), ("textasciicircum", "^"), ("hat", "^")
+];
+
+
+/// Returns the separator of paragraphs in the ``markup`` language, e.g., of consecutive responses.
+pub fn paragraph_break(markup: Markup) -> &'static str {
+    match markup {
+        Markup::Plain | Markup::Markdown => "\n\n",
+        Markup::Html => "\n<br><br>\n"
+    }
+}
+
+
+/// Converts the LaTeX ``text`` to the ``markup`` language.
+pub fn convert_latex(text: &str, markup: Markup) -> String {
+    Parser::new(text, markup).parse().into_iter().map(|run| run.text).collect()
+}
+
+
+/// Converts the LaTeX ``text`` to plain text, split into runs of the same style.
+/// This is used by formats that store styles separately from the text (e.g., DOCX).
+pub fn latex_runs(text: &str) -> Vec<Run> {
+    Parser::new(text, Markup::Plain).parse()
+}
+
+
+/// Escapes the characters of ``text`` that have a special meaning in the ``markup`` language.
+pub fn escape(text: &str, markup: Markup) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        output += &escape_char(c, markup, output.is_empty() || output.ends_with('\n'));
+    }
+    output
+}
+
+
+/// Escapes the character ``c``, which is at the start of a line when ``line_start``
+/// (where ``-`` and ``+`` start a Markdown list item).
+fn escape_char(c: char, markup: Markup, line_start: bool) -> String {
+    match (markup, c) {
+        (Markup::Html, '&') => "&amp;".to_string(),
+        (Markup::Html, '<') => "&lt;".to_string(),
+        (Markup::Html, '>') => "&gt;".to_string(),
+        (Markup::Html, '"') => "&quot;".to_string(),
+        (Markup::Markdown, '*' | '_' | '`' | '\\' | '[' | ']' | '<' | '>' | '#') => format!("\\{c}"),
+        (Markup::Markdown, '-' | '+') if line_start => format!("\\{c}"),
+        _ => c.to_string()
+    }
+}
+
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    markup: Markup,
+    math: bool,
+    bold: bool,
+    italic: bool,
+    /// Text of the current run.
+    output: String,
+    runs: Vec<Run>,
+    /// The open lists, from the outermost.
+    lists: Vec<List>
+}
+
+
+/// An open list environment.
+struct List {
+    /// Whether the items are numbered (``enumerate``).
+    ordered: bool,
+    /// Number of the list's items so far.
+    items: usize
+}
+
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, markup: Markup) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            markup,
+            math: false,
+            bold: false,
+            italic: false,
+            output: String::new(),
+            runs: Vec::new(),
+            lists: Vec::new()
+        }
+    }
+
+    /// Converts the whole text.
+    fn parse(mut self) -> Vec<Run> {
+        self.group(false);
+        self.flush();
+        self.runs
+    }
+
+    /// Checks whether the output is at the start of a line.
+    fn line_start(&self) -> bool {
+        match self.output.chars().last().or_else(|| self.runs.last()?.text.chars().last()) {
+            Some(c) => c == '\n',
+            None => true
+        }
+    }
+
+    /// Escapes the character ``c`` at the end of the output.
+    fn push_escaped(&mut self, c: char) {
+        let escaped = escape_char(c, self.markup, self.line_start());
+        self.output += &escaped;
+    }
+
+    /// Ends the current run.
+    fn flush(&mut self) {
+        if !self.output.is_empty() {
+            self.runs.push(Run { text: mem::take(&mut self.output), bold: self.bold, italic: self.italic });
+        }
+    }
+
+    /// Converts the text up to the end, or to the closing brace of the group when ``nested``.
+    fn group(&mut self, nested: bool) {
+        while let Some(c) = self.chars.next() {
+            match c {
+                '}' if nested => break,
+                '}' => {},  // Unbalanced brace
+                '{' => self.group(true),
+                '\\' => self.command(),
+                '$' => self.math = !self.math,
+                '~' => self.output.push('\u{a0}'),
+                // A single line break is a space, an empty line ends the paragraph
+                '\n' => {
+                    let mut breaks = 1;
+                    while let Some(c) = self.chars.next_if(|c| c.is_whitespace()) {
+                        breaks += usize::from(c == '\n');
+                    }
+                    match breaks {
+                        1 => self.output.push(' '),
+                        _ => self.paragraph_break()
+                    }
+                }
+                '-' if !self.math && self.chars.peek() == Some(&'-') => {
+                    self.chars.next();
+                    if self.chars.next_if_eq(&'-').is_some() {
+                        self.output.push('—');
+                    }
+                    else {
+                        self.output.push('–');
+                    }
+                }
+                '`' if self.chars.next_if_eq(&'`').is_some() => self.output.push('„'),
+                '\'' if self.chars.next_if_eq(&'\'').is_some() => self.output.push('“'),
+                c => self.push_escaped(c)
+            }
+        }
+    }
+
+    /// Converts the command following a backslash.
+    fn command(&mut self) {
+        let Some(first) = self.chars.next() else {
+            return;
+        };
+
+        // Escaped character or a single-character command
+        if !first.is_ascii_alphabetic() {
+            match first {
+                '\\' => self.line_break(),
+                ',' | ';' | ' ' => self.output.push(' '),
+                '!' => {},
+                c => self.push_escaped(c)
+            }
+            return;
+        }
+
+        let mut name = first.to_string();
+        while let Some(c) = self.chars.next_if(char::is_ascii_alphabetic) {
+            name.push(c);
+        }
+
+        if let Some((_, symbol)) = SYMBOLS.iter().find(|(command, _)| *command == name) {
+            // Skip the space that terminates the command name
+            self.chars.next_if_eq(&' ');
+            self.output += &escape(symbol, self.markup);
+            return;
+        }
+
+        match name.as_str() {
+            "textbf" => self.styled(Style::Bold),
+            "textit" | "emph" | "textsl" => self.styled(Style::Italic),
+            "newline" | "linebreak" => self.line_break(),
+            "par" => {
+                self.skip_whitespace();
+                self.paragraph_break();
+            }
+            "begin" => {
+                let environment = self.environment();
+                self.begin(&environment);
+            }
+            "end" => {
+                let environment = self.environment();
+                self.end(&environment);
+            }
+            "item" => self.item(),
+            _ => {
+                // Unknown command, keep the content of its arguments
+                let mut arguments = false;
+                while self.chars.next_if_eq(&'{').is_some() {
+                    self.group(true);
+                    arguments = true;
+                }
+                if !arguments {
+                    self.chars.next_if_eq(&' ');
+                }
+            }
+        }
+    }
+
+    /// Converts the argument of a style command.
+    fn styled(&mut self, style: Style) {
+        let (open, close) = match (self.markup, style) {
+            (Markup::Plain, _) => ("", ""),
+            (Markup::Markdown, Style::Bold) => ("**", "**"),
+            (Markup::Markdown, Style::Italic) => ("*", "*"),
+            (Markup::Html, Style::Bold) => ("<strong>", "</strong>"),
+            (Markup::Html, Style::Italic) => ("<em>", "</em>")
+        };
+
+        self.output += open;
+        self.flush();
+        let (bold, italic) = (self.bold, self.italic);
+        match style {
+            Style::Bold => self.bold = true,
+            Style::Italic => self.italic = true
+        }
+        if self.chars.next_if_eq(&'{').is_some() {
+            self.group(true);
+        }
+        self.flush();
+        (self.bold, self.italic) = (bold, italic);
+        self.output += close;
+    }
+
+    fn line_break(&mut self) {
+        self.output += match self.markup {
+            Markup::Plain => "\n",
+            Markup::Markdown => "  \n",
+            Markup::Html => "<br>\n"
+        };
+    }
+
+    fn paragraph_break(&mut self) {
+        self.trim_end();
+        self.output += paragraph_break(self.markup);
+    }
+
+    /// Removes the spaces at the end of the current run (e.g., before a paragraph or list item).
+    fn trim_end(&mut self) {
+        self.output.truncate(self.output.trim_end_matches(' ').len());
+    }
+
+    /// Reads the environment name argument of ``\begin`` or ``\end``.
+    fn environment(&mut self) -> String {
+        let mut name = String::new();
+        if self.chars.next_if_eq(&'{').is_some() {
+            name = self.chars.by_ref().take_while(|c| *c != '}').collect();
+        }
+        self.skip_whitespace();
+        name
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    /// Starts the ``environment``. Lists are converted, other environments only keep their content.
+    fn begin(&mut self, environment: &str) {
+        let ordered = match environment {
+            "itemize" => false,
+            "enumerate" => true,
+            _ => return
+        };
+        self.trim_end();
+        let open = match (self.markup, ordered) {
+            (Markup::Html, false) => "<ul>",
+            (Markup::Html, true) => "<ol>",
+            _ if self.line_start() => "",
+            _ => "\n"
+        };
+        self.output += open;
+        self.lists.push(List { ordered, items: 0 });
+    }
+
+    /// Ends the ``environment`` (see [`Parser::begin`]).
+    fn end(&mut self, environment: &str) {
+        if !matches!(environment, "itemize" | "enumerate") {
+            return;
+        }
+        let Some(list) = self.lists.pop() else {
+            return;
+        };
+        self.trim_end();
+        if self.markup == Markup::Html && list.items > 0 {
+            self.output += "</li>";
+        }
+        self.output += match (self.markup, list.ordered) {
+            (Markup::Html, false) => "</ul>",
+            (Markup::Html, true) => "</ol>",
+            _ => "\n"
+        };
+    }
+
+    /// Starts an item of the innermost list.
+    fn item(&mut self) {
+        self.skip_whitespace();
+        self.trim_end();
+        let depth = self.lists.len();
+        let Some(list) = self.lists.last_mut() else {
+            return;
+        };
+        list.items += 1;
+        let (ordered, items) = (list.ordered, list.items);
+
+        if self.markup == Markup::Html {
+            self.output += if items == 1 { "<li>" } else { "</li><li>" };
+            return;
+        }
+        if !self.line_start() {
+            self.output.push('\n');
+        }
+        self.output += &"  ".repeat(depth - 1);
+        match ordered {
+            true => self.output += &format!("{items}. "),
+            false => self.output += "- "
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKUPS: [Markup; 3] = [Markup::Plain, Markup::Markdown, Markup::Html];
+
+    /// Converts the LaTeX ``text`` to every markup language (plain text, Markdown, HTML).
+    fn convert(text: &str) -> [String; 3] {
+        MARKUPS.map(|markup| convert_latex(text, markup))
+    }
+
+    #[test]
+    fn converts_styles() {
+        assert_eq!(convert("Zelo \\textbf{dobro} in \\emph{jasno}, \\textit{res}."), [
+            "Zelo dobro in jasno, res.",
+            "Zelo **dobro** in *jasno*, *res*.",
+            "Zelo <strong>dobro</strong> in <em>jasno</em>, <em>res</em>."
+        ]);
+        assert_eq!(latex_runs("a \\textbf{b}"), [
+            Run { text: "a ".to_string(), bold: false, italic: false },
+            Run { text: "b".to_string(), bold: true, italic: false }
+        ]);
+    }
+
+    #[test]
+    fn converts_symbols_and_escaped_characters() {
+        assert_eq!(convert("4,5 $\\pm$ 0,3 -- 100\\% \\& \\#1 \\_ \\{x\\} a\\\\b"), [
+            "4,5 ± 0,3 – 100% & #1 _ {x} a\nb",
+            "4,5 ± 0,3 – 100% & \\#1 \\_ {x} a  \nb",
+            "4,5 ± 0,3 – 100% &amp; #1 _ {x} a<br>\nb"
+        ]);
+    }
+
+    #[test]
+    fn escapes_markdown() {
+        assert_eq!(convert_latex("*a* [b](c) <d> `e` 1_2", Markup::Markdown), "\\*a\\* \\[b\\](c) \\<d\\> \\`e\\` 1\\_2");
+        assert_eq!(convert_latex("- a\n\n+ b -- c - d", Markup::Markdown), "\\- a\n\n\\+ b – c - d");
+        assert_eq!(escape("-1 # x", Markup::Markdown), "\\-1 \\# x");
+        assert_eq!(escape("a < \"b\" & c", Markup::Html), "a &lt; &quot;b&quot; &amp; c");
+        assert_eq!(escape("*a*", Markup::Plain), "*a*");
+    }
+
+    #[test]
+    fn converts_paragraph_breaks() {
+        let text = "Prvi odstavek,\nše vedno prvi.\n\n  Drugi.\\par Tretji.";
+        assert_eq!(convert(text), [
+            "Prvi odstavek, še vedno prvi.\n\nDrugi.\n\nTretji.",
+            "Prvi odstavek, še vedno prvi.\n\nDrugi.\n\nTretji.",
+            "Prvi odstavek, še vedno prvi.\n<br><br>\nDrugi.\n<br><br>\nTretji."
+        ]);
+    }
+
+    #[test]
+    fn converts_lists() {
+        let text = "Prednosti:\n\\begin{itemize}\n  \\item jasna \\textbf{razlaga}\n  \\item primeri\n\\end{itemize}\nKoraki:\n\\begin{enumerate}\\item prvi \\item drugi\\end{enumerate}";
+        assert_eq!(convert(text), [
+            "Prednosti:\n- jasna razlaga\n- primeri\nKoraki:\n1. prvi\n2. drugi\n",
+            "Prednosti:\n- jasna **razlaga**\n- primeri\nKoraki:\n1. prvi\n2. drugi\n",
+            "Prednosti:<ul><li>jasna <strong>razlaga</strong></li><li>primeri</li></ul>Koraki:<ol><li>prvi</li><li>drugi</li></ol>"
+        ]);
+    }
+}
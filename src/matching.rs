@@ -0,0 +1,197 @@
+//! Matching of question texts between the responses file and the STUDIS export.
+//!
+//! STUDIS occasionally changes the punctuation, gender markers (e.g., "izvajalca/ke") or whitespace of its
+//! questions. A question is therefore matched (in this order) by its exact text, by one of its aliases,
+//! by its normalized text (see [`normalize`]) and finally by the most similar text, if the similarity
+//! (see [`similarity`]) reaches the given threshold.
+
+use unicode_normalization::UnicodeNormalization;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::error::{Error, Result};
+use crate::responses::Question;
+
+
+/// How a question was matched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchKind {
+    /// The texts are equal.
+    Exact,
+    /// One of the question's aliases equals the text.
+    Alias,
+    /// The texts are equal after normalization.
+    Normalized,
+    /// The texts are similar. Contains the similarity (between 0 and 1).
+    Fuzzy(f64)
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact => write!(f, "exact"),
+            Self::Alias => write!(f, "alias"),
+            Self::Normalized => write!(f, "normalized"),
+            Self::Fuzzy(similarity) => write!(f, "similarity {similarity:.2}")
+        }
+    }
+}
+
+
+/// A question of the responses file, matched to a question of the STUDIS export.
+#[derive(Clone, Debug)]
+pub struct QuestionMatch {
+    /// Text of the question in the responses file.
+    pub question: String,
+    /// Text of the matched question in the STUDIS export.
+    pub csv_question: String,
+    /// Index of the matched question in the STUDIS export.
+    pub index: usize,
+    /// How the question was matched.
+    pub kind: MatchKind
+}
+
+
+/// Parses the minimum similarity (between 0 and 1) of a fuzzy match, e.g., of the ``--similarity`` option.
+pub fn parse_similarity(text: &str) -> Result<f64> {
+    let threshold = text.trim().parse().map_err(|_| Error::Input(format!(
+        "invalid similarity \"{text}\" (expected a number between 0 and 1)"
+    )))?;
+    validate_similarity(threshold)?;
+    Ok(threshold)
+}
+
+
+/// Checks that the minimum similarity ``threshold`` of a fuzzy match is between 0 and 1.
+pub fn validate_similarity(threshold: f64) -> Result<()> {
+    match (0.0..=1.0).contains(&threshold) {
+        true => Ok(()),
+        false => Err(Error::Input(format!("similarity {threshold} is not between 0 and 1")))
+    }
+}
+
+
+/// Normalizes the ``text`` for comparison: applies Unicode (NFKC) normalization, converts it to
+/// lowercase and replaces punctuation and whitespace runs with a single space.
+pub fn normalize(text: &str) -> String {
+    let folded: String = text.nfkc()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+
+/// Returns the similarity (between 0 and 1) of the normalized texts ``a`` and ``b``.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    strsim::normalized_levenshtein(&normalize(a), &normalize(b))
+}
+
+
+/// Finds the ``text`` (or one of its ``aliases``) among the ``candidates``.
+/// Returns the index of the matched candidate and how it was matched.
+/// A fuzzy match is only accepted when its similarity is at least ``threshold``.
+pub fn find(candidates: &[String], text: &str, aliases: &[String], threshold: f64) -> Option<(usize, MatchKind)> {
+    if let Some(i) = candidates.iter().position(|c| c == text) {
+        return Some((i, MatchKind::Exact));
+    }
+    if let Some(i) = candidates.iter().position(|c| aliases.contains(c)) {
+        return Some((i, MatchKind::Alias));
+    }
+
+    let texts: Vec<String> = std::iter::once(text).chain(aliases.iter().map(String::as_str)).map(normalize).collect();
+    let normalized: Vec<String> = candidates.iter().map(|c| normalize(c)).collect();
+    if let Some(i) = normalized.iter().position(|c| texts.contains(c)) {
+        return Some((i, MatchKind::Normalized));
+    }
+
+    normalized.iter().enumerate()
+        .map(|(i, c)| (i, texts.iter().map(|t| strsim::normalized_levenshtein(t, c)).fold(0.0, f64::max)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .filter(|(_, similarity)| *similarity >= threshold)
+        .map(|(i, similarity)| (i, MatchKind::Fuzzy(similarity)))
+}
+
+
+/// Matches each of the ``questions`` to the ``csv_questions`` of a STUDIS section.
+/// All the questions that cannot be matched are reported at once, together with the most similar CSV question.
+/// Each CSV question can be matched by a single question, otherwise the questions are reported as ambiguous.
+pub fn match_questions(csv_questions: &[String], questions: &[Question], threshold: f64) -> Result<Vec<QuestionMatch>> {
+    validate_similarity(threshold)?;
+    let mut matches = Vec::with_capacity(questions.len());
+    let mut missing = Vec::new();
+    for question in questions {
+        match find(csv_questions, &question.text, &question.aliases, threshold) {
+            Some((index, kind)) => matches.push(QuestionMatch {
+                question: question.text.clone(),
+                csv_question: csv_questions[index].clone(),
+                index,
+                kind
+            }),
+            None => missing.push(match closest(csv_questions, &question.text) {
+                Some((closest, similarity)) => format!(
+                    "\"{}\" (closest: \"{closest}\", similarity {similarity:.2})", question.text
+                ),
+                None => format!("\"{}\"", question.text)
+            })
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(Error::Survey(format!("CSV is missing categories {}", missing.join(", "))));
+    }
+
+    let mut matched_by: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    for matched in &matches {
+        matched_by.entry(matched.index).or_default().push(&matched.question);
+    }
+    let ambiguous: Vec<String> = matched_by.into_iter()
+        .filter(|(_, questions)| questions.len() > 1)
+        .map(|(index, questions)| format!("\"{}\" (matched by \"{}\")", csv_questions[index], questions.join("\", \"")))
+        .collect();
+    if !ambiguous.is_empty() {
+        return Err(Error::Survey(format!(
+            "CSV categories {} are matched by several questions. Add aliases or increase the similarity.",
+            ambiguous.join(", ")
+        )));
+    }
+
+    Ok(matches)
+}
+
+
+/// Returns the candidate most similar to ``text`` and its similarity.
+pub fn closest<'a>(candidates: &'a [String], text: &str) -> Option<(&'a str, f64)> {
+    candidates.iter()
+        .map(|c| (c.as_str(), similarity(c, text)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(text: &str) -> Question {
+        Question { text: text.to_string(), aliases: Vec::new(), buckets: Vec::new() }
+    }
+
+    #[test]
+    fn matches_one_to_one() {
+        let csv_questions = ["Izvajalec je pripravljen.".to_string(), "Izvajalec je dosegljiv.".to_string()];
+        let matches = match_questions(&csv_questions, &[question("Izvajalec je dosegljiv")], 0.9).unwrap();
+        assert_eq!(matches[0].index, 1);
+        assert_eq!(matches[0].kind, MatchKind::Normalized);
+
+        let questions = [question("Izvajalec je dosegljiv."), question("Izvajalec je dosegljiv")];
+        assert!(matches!(match_questions(&csv_questions, &questions, 0.9), Err(Error::Survey(_))));
+    }
+
+    #[test]
+    fn parses_similarity() {
+        assert_eq!(parse_similarity("0.8").unwrap(), 0.8);
+        assert!(parse_similarity("1.5").is_err());
+        assert!(parse_similarity("NaN").is_err());
+    }
+}
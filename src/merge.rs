@@ -1,58 +1,94 @@
-/// Module of the ``merge`` command
-use crate::preproc::{extract_section_columns, preprocess_candidate_csv};
-use crate::fs::read_file_universal;
-use std::collections::HashMap;
-use std::path::PathBuf;
+//! Module of the ``merge`` command
+use crate::preproc::{
+    QuestionRow, Section, SurveyExport, SurveyOptions,
+    COUNT_COLUMN, MEAN_COLUMN, QUESTION_COLUMN, STD_COLUMN
+};
+use crate::error::{Error, Result};
+use crate::config;
+use crate::matching::{self, MatchKind};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::io::Write;
 use std::fs::File;
-use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
 use glob::glob;
-use csv;
 
 
-const C_QUESTION_KEY: &str = "Vprašanje";
-const C_MEAN_KEY: &str = "Povprečje";
-const C_STD_KEY: &str = "Standardni odklon";
 const C_PRECISION: usize = 2;
 
 
-/// Accepts a ``file`` parameter, which is a path, preprocesses it and returns a mapping
-/// that maps a STUDIS question to the mean grade.
-fn csv_parse_question_means(file: &PathBuf, section: &str) -> Result<HashMap<String, f64>> {
-    let mut mapping = HashMap::new();
-    let sections = preprocess_candidate_csv(
-        read_file_universal(file).with_context(|| format!("unable to read file ({})", file.display()))?
-    );
-    let extracted = extract_section_columns(sections, section)?;
-    let questions = extracted.get(C_QUESTION_KEY).with_context(|| format!("failed to find key {C_QUESTION_KEY} in CSV"))?;
-    let means = extracted.get(C_MEAN_KEY).with_context(|| format!("failed to find key {C_MEAN_KEY} in CSV"))?;
-    let mut mean;
-    let mut smean;
-    for (i, question) in questions.into_iter().enumerate() {
-        smean = means.get(i).with_context(|| "CSV question column is empty or not found")?;
-        mean = smean.parse().with_context(|| format!("could not parse mean value ({smean})"))?;
-        mapping.insert(question.clone(), mean);
-    }
+/// How the means of different files are weighted when merged.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeWeighting {
+    /// Each file counts the same, regardless of its number of respondents.
+    Equal,
+    /// Each file's mean is weighted by its number of respondents (``Število odgovorov``).
+    Respondents
+}
+
 
-    Ok(mapping)
+/// How the standard deviation of the merged files is calculated.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StdMode {
+    /// The (weighted) spread of the per-file means. Each file's own standard deviation is ignored.
+    Means,
+    /// The standard deviation of all respondents together, combined from the per-file means,
+    /// standard deviations and respondent counts (pooled variance).
+    Pooled
 }
 
 
-/// Command processing function for the ``merge`` command.
-pub fn command_merge(file_patterns: &Vec<PathBuf>, section: &str, output: &PathBuf) -> Result<()> {
-    const MIN_FILES_TO_MATCH: usize = 2;
+/// Options of the ``merge`` command.
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    /// Name of the grades section to use (e.g., "Anketa o izvajalcu").
+    pub section: String,
+    /// How the means of different files are weighted.
+    pub weighting: MergeWeighting,
+    /// How the merged standard deviation is calculated.
+    pub std_mode: StdMode,
+    /// Minimum similarity (between 0 and 1) of question texts in different files for a fuzzy match
+    /// (see [`crate::matching`]).
+    pub similarity: f64,
+    /// Options of reading the STUDIS exports (e.g., their encoding).
+    pub survey: SurveyOptions
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            section: config::merge::SECTION_DEFAULT.to_string(),
+            weighting: config::merge::WEIGHTING_DEFAULT,
+            std_mode: config::merge::STD_MODE_DEFAULT,
+            similarity: config::merge::SIMILARITY_DEFAULT,
+            survey: SurveyOptions::default()
+        }
+    }
+}
 
-    let mut qvalues: HashMap<String, Vec<f64>> = HashMap::new();  // Question values
 
-    // Iterate all files and create a mapping that maps a question to a vector of mean values.
+/// Accepts a ``file`` parameter, which is a path, reads it with the ``options`` (see [`SurveyExport::from_file`])
+/// and returns a mapping that maps a STUDIS question of the ``section`` to its grades.
+pub fn csv_parse_question_grades(
+    file: &Path,
+    section: &str,
+    options: &SurveyOptions
+) -> Result<HashMap<String, QuestionRow>> {
+    let survey = SurveyExport::from_file(file, options)?;
+    Ok(survey.section(section)?.rows.iter().map(|row| (row.question.clone(), row.clone())).collect())
+}
+
+
+/// Expands the glob ``file_patterns`` into a list of files.
+/// Files matched by different patterns are all combined into the same list.
+pub fn expand_patterns(file_patterns: &[PathBuf]) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
     let mut matches;
     let mut p;
 
-    // Combine all the file patterns together
     for pattern in file_patterns {
-        p = pattern.to_str().with_context(|| format!("{} contains invalid unicode!", pattern.display()))?;
-        matches = glob(p).with_context(|| "invalid pattern was given")?;
+        p = pattern.to_str().ok_or_else(|| Error::Input(format!("{} contains invalid unicode!", pattern.display())))?;
+        matches = glob(p).map_err(|e| Error::Input(format!("invalid pattern was given ({p}): {e}")))?;
 
         for entry in matches {
             match entry {
@@ -62,42 +98,287 @@ pub fn command_merge(file_patterns: &Vec<PathBuf>, section: &str, output: &PathB
         }
     }
 
+    Ok(files)
+}
+
+
+/// Calculates the standard deviation of all respondents of the ``values`` together.
+/// The combined (sample) variance consists of the variance within each file and the variance between
+/// the file means and the combined mean, which is weighted by respondent counts.
+/// Each of the ``values`` must have both the respondent count and the standard deviation.
+pub fn pooled_std(values: &[QuestionRow]) -> Result<f64> {
+    let values = values.iter()
+        .map(|v| match (v.count, v.std) {
+            (Some(count), Some(std)) => Ok((count as f64, v.mean, std)),
+            _ => Err(Error::Input(format!(
+                "pooled standard deviation of question \"{}\" requires its respondent count and standard deviation",
+                v.question
+            )))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let n_total = values.iter().map(|(n, _, _)| n).sum::<f64>();
+    if n_total <= 1.0 {
+        return Ok(0.0);
+    }
+
+    let mean = values.iter().map(|(n, mean, _)| n * mean).sum::<f64>() / n_total;
+    let within = values.iter().map(|(n, _, std)| (n - 1.0).max(0.0) * std.powi(2)).sum::<f64>();
+    let between = values.iter().map(|(n, m, _)| n * (m - mean).powi(2)).sum::<f64>();
+    Ok(((within + between) / (n_total - 1.0)).sqrt())
+}
+
+
+/// A question of a file, merged with a question of a previous file, whose text differs.
+#[derive(Clone, Debug)]
+pub struct MergeMatch {
+    /// The file of the question.
+    pub file: PathBuf,
+    /// Text of the question in ``file``.
+    pub question: String,
+    /// Text of the question of a previous file, under which the question is merged.
+    pub merged_question: String,
+    /// How the question was matched.
+    pub kind: MatchKind
+}
+
+
+/// Merged grades of the files.
+#[derive(Clone, Debug)]
+pub struct MergedFiles {
+    /// The merged questions, sorted by their text.
+    pub questions: Vec<MergedQuestion>,
+    /// Questions whose texts differ between the files, which are merged nonetheless.
+    pub matches: Vec<MergeMatch>
+}
+
+
+/// Merged grades of a single question.
+#[derive(Clone, Debug)]
+pub struct MergedQuestion {
+    /// Text of the question.
+    pub question: String,
+    /// Total number of respondents. ``None`` if any of the files has no respondent count.
+    pub count: Option<u64>,
+    /// The (weighted) mean over the files.
+    pub mean: f64,
+    /// Standard deviation, calculated according to [`MergeOptions::std_mode`].
+    pub std: f64,
+    /// The largest number of decimals of the files (see [`QuestionRow::decimals`]).
+    pub decimals: usize
+}
+
+
+/// Merges the grades of the ``files``' section [`MergeOptions::section`].
+/// Questions with different texts in different files are matched by [`MergeOptions::similarity`]
+/// and merged under the text of the first file (see [`MergedFiles::matches`]).
+pub fn merge_files(files: &[PathBuf], options: &MergeOptions) -> Result<MergedFiles> {
+    matching::validate_similarity(options.similarity)?;
+    let section = options.section.as_str();
+    let mut matches = Vec::new();
+    let mut qvalues: BTreeMap<String, Vec<QuestionRow>> = BTreeMap::new();  // Question values
+
+    // Create grade mapping that maps Question => [grades of each file]
+    for file in files {
+        let mut fgrades: Vec<_> = csv_parse_question_grades(file, section, &options.survey)?.into_iter().collect();
+        fgrades.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut unmatched: Vec<String> = qvalues.keys().cloned().collect();  // Questions not yet in this file
+        for (question, grades) in fgrades {
+            if (options.weighting == MergeWeighting::Respondents || options.std_mode == StdMode::Pooled)
+                && grades.count.is_none()
+            {
+                return Err(Error::Survey(format!(
+                    "weighting by respondents and pooled standard deviation require the \"{COUNT_COLUMN}\" column, \
+                    which is missing in {}",
+                    file.display()
+                )));
+            }
+            if options.std_mode == StdMode::Pooled && grades.std.is_none() {
+                return Err(Error::Survey(format!(
+                    "pooled standard deviation requires the \"{STD_COLUMN}\" column, which is missing in {}",
+                    file.display()
+                )));
+            }
+
+            // Questions are matched to the questions of previous files, as their texts may have changed.
+            let key = match matching::find(&unmatched, &question, &[], options.similarity) {
+                Some((i, kind)) => {
+                    if kind != MatchKind::Exact {
+                        matches.push(MergeMatch {
+                            file: file.clone(),
+                            question: question.clone(),
+                            merged_question: unmatched[i].clone(),
+                            kind
+                        });
+                    }
+                    unmatched.remove(i)
+                }
+                None => question
+            };
+            qvalues.entry(key).or_default().push(grades);
+        }
+    }
+
+    // Calculate the (weighted) mean value over files of question means,
+    // standard deviation and the total number of respondents.
+    let mut qmerged = Vec::with_capacity(qvalues.len());
+    for (question, values) in qvalues {
+        let weights: Vec<f64> = match options.weighting {
+            MergeWeighting::Equal => vec![1.0; values.len()],
+            MergeWeighting::Respondents => values.iter().map(|v| v.count.unwrap_or(0) as f64).collect()
+        };
+        let weight_sum = weights.iter().sum::<f64>();
+        if weight_sum == 0.0 {
+            return Err(Error::Survey(format!("question \"{question}\" has no respondents in any of the files")));
+        }
+
+        // Mean calculated over file question means.
+        let mean = values.iter().zip(&weights).map(|(v, w)| v.mean * w).sum::<f64>() / weight_sum;
+        let std = match options.std_mode {
+            // Standard deviation calculated over file question means.
+            StdMode::Means => (
+                values.iter().zip(&weights).map(|(v, w)| w * (v.mean - mean).powi(2)).sum::<f64>() / weight_sum
+            ).sqrt(),
+            StdMode::Pooled => pooled_std(&values)?
+        };
+        // Total respondents, if known for all files.
+        let count = values.iter().map(|v| v.count).sum::<Option<u64>>();
+        let decimals = values.iter().map(|v| v.decimals).max().unwrap_or_default();
+        qmerged.push(MergedQuestion { question, count, mean: (mean * 1000.0).round() / 1000.0, std, decimals });
+    }
+
+    Ok(MergedFiles { questions: qmerged, matches })
+}
+
+
+/// Returns the ``merged`` questions as the STUDIS section ``title``, rounded to their decimals
+/// (see [`MergedQuestion::decimals`]).
+pub fn merged_section(title: &str, merged: &[MergedQuestion]) -> Section {
+    Section {
+        title: title.to_string(),
+        rows: merged.iter().map(|row| {
+            let round = |value: f64| format!("{value:.*}", row.decimals).parse().unwrap_or(value);
+            QuestionRow {
+                question: row.question.clone(),
+                count: row.count,
+                mean: round(row.mean),
+                std: Some(round(row.std)),
+                decimals: row.decimals
+            }
+        }).collect()
+    }
+}
+
+
+/// Formats the ``merged`` questions as a CSV table of a STUDIS section (without the section's title),
+/// which can be read as a section of a [`SurveyExport`].
+pub fn merged_csv(merged: &[MergedQuestion]) -> Result<String> {
+    // Semicolons are used as delimiters, so that the commas in the question texts don't need quoting.
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').from_writer(Vec::new());
+    writer.write_record([QUESTION_COLUMN, COUNT_COLUMN, MEAN_COLUMN, STD_COLUMN])?;
+    for row in merged {
+        // Write record in format (question, respondents, mean (rounded to 2 decimals), std (rounded to 2 decimals))
+        writer.write_record([
+            &row.question,
+            &row.count.map(|c| c.to_string()).unwrap_or_default(),
+            &format!("{:.1$}", row.mean, C_PRECISION),
+            &format!("{:.1$}", row.std, C_PRECISION)
+        ])?;
+    }
+    let data = writer.into_inner().map_err(|e| csv::Error::from(e.into_error()))?;
+    Ok(String::from_utf8(data).expect("CSV is written from UTF-8 strings"))
+}
+
+
+/// Command processing function for the ``merge`` command.
+/// Returns the questions whose texts differ between the files (see [`MergedFiles::matches`]).
+pub fn command_merge(file_patterns: &[PathBuf], output: &Path, options: &MergeOptions) -> Result<Vec<MergeMatch>> {
+    const MIN_FILES_TO_MATCH: usize = 2;
+
+    // Expand the patterns into the list of files to merge.
+    let files = expand_patterns(file_patterns)?;
     if files.len() < MIN_FILES_TO_MATCH {
-        return Err(anyhow!(
+        return Err(Error::Input(format!(
             "{file_patterns:?} together need to match at least {MIN_FILES_TO_MATCH} files, but they matched {}.",
             files.len()
-        ));
+        )));
     }
 
-    // Create mean grade mapping that maps Question => [mean grade of each file]
-    for file in &files {
-        for (question, mean) in csv_parse_question_means(file, section)? {
-            qvalues.entry(question).or_insert_with(Vec::new).push(mean);
+    let merged = merge_files(&files, options)?;
+    let table = merged_csv(&merged.questions)?;
+    let mut file = File::create(output).map_err(|e| Error::io("unable to open file", output, e))?;
+    file.write_all(format!("{}\n{table}", options.section).as_bytes())
+        .map_err(|e| Error::io("unable to write merged CSV", output, e))?;
+    Ok(merged.matches)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a STUDIS export with the section "Anketa o izvajalcu" of the ``rows``
+    /// (question, respondent count, mean and standard deviation) into the ``directory``.
+    fn export(directory: &Path, name: &str, rows: &[(&str, &str, &str, &str)]) -> PathBuf {
+        let mut content = format!("Anketa o izvajalcu\t\t\t\n{QUESTION_COLUMN}\t{COUNT_COLUMN}\t{MEAN_COLUMN}\t{STD_COLUMN}\n");
+        for (question, count, mean, std) in rows {
+            content += &format!("{question}\t{count}\t{mean}\t{std}\n");
         }
+        let path = directory.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
     }
 
-    // Create a mapping that maps question to the mean value (over files) of question means and standard deviation.
-    let mut qmerged = HashMap::new();
-    for (question, values) in qvalues {
-        // Mean calculated over file question means.
-        let mean = values.iter().sum::<f64>() / values.len() as f64;
-        // Standard deviation calculated over file question means.
-        let std = (values.iter().map(|num| (num - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
-        qmerged.insert(
-            question, 
-            ((mean * 1000.0).round() / 1000.0, std)
-        );
+    fn row(count: Option<u64>, mean: f64, std: Option<f64>) -> QuestionRow {
+        QuestionRow { question: "Vprašanje".to_string(), count, mean, std, decimals: 2 }
     }
 
-    let mut file = File::create(output).with_context(|| format!("unable to open file '{}'", output.display()))?;
-    file.write_all(section.as_bytes()).with_context(|| "unable to write grades section title")?;
-    file.write_all("\n".as_bytes())?;
-    let mut writer = csv::Writer::from_writer(file);
-    writer.write_record(&[C_QUESTION_KEY, C_MEAN_KEY, C_STD_KEY]).with_context(|| "unable to write header")?;
-    for (k, (mean,  std)) in &qmerged {
-        // Write record in format (question, mean (rounded to 4 decimals), std (rounded to 4 decimals))
-        writer.write_record(&[k, &format!("{mean:.0$}", C_PRECISION), &format!("{std:.0$}", C_PRECISION)])
-            .with_context(|| format!("unable to write record (key = {k})"))?;
+    #[test]
+    fn pools_std() {
+        // Within: 9 * 1^2 + 19 * 0.5^2 = 13.75, between: 10 * (4 - 14/3)^2 + 20 * (5 - 14/3)^2 = 20/3
+        let std = pooled_std(&[row(Some(10), 4.0, Some(1.0)), row(Some(20), 5.0, Some(0.5))]).unwrap();
+        assert!((std - ((13.75 + 20.0 / 3.0) / 29.0f64).sqrt()).abs() < 1e-12);
+        assert!((std - 0.83906).abs() < 1e-5);
+
+        // A single respondent (or none) has no spread
+        assert_eq!(pooled_std(&[row(Some(1), 4.0, Some(0.0))]).unwrap(), 0.0);
+        assert_eq!(pooled_std(&[row(Some(0), 4.0, Some(0.0)), row(Some(1), 5.0, Some(0.0))]).unwrap(), 0.0);
+
+        assert!(matches!(pooled_std(&[row(None, 4.0, Some(1.0))]), Err(Error::Input(_))));
+        assert!(matches!(pooled_std(&[row(Some(10), 4.0, None)]), Err(Error::Input(_))));
+    }
+
+    #[test]
+    fn weights_means() {
+        let directory = tempfile::tempdir().unwrap();
+        let files = [
+            export(directory.path(), "a.csv", &[("Vprašanje", "10", "4,00", "1,00")]),
+            export(directory.path(), "b.csv", &[("Vprašanje", "30", "5,00", "0,50")])
+        ];
+
+        let equal = &merge_files(&files, &MergeOptions::default()).unwrap().questions[0];
+        assert_eq!((equal.count, equal.mean), (Some(40), 4.5));
+        assert!((equal.std - 0.5).abs() < 1e-12);
+
+        let options = MergeOptions { weighting: MergeWeighting::Respondents, ..Default::default() };
+        let respondents = &merge_files(&files, &options).unwrap().questions[0];
+        assert_eq!((respondents.count, respondents.mean), (Some(40), 4.75));
+        assert!((respondents.std - 0.1875f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn keeps_decimals_of_merged_means() {
+        let directory = tempfile::tempdir().unwrap();
+        let files = [
+            export(directory.path(), "a.csv", &[("Vprašanje", "10", "4,50", "0,50")]),
+            export(directory.path(), "b.csv", &[("Vprašanje", "20", "4,83", "0,4")])
+        ];
+        let merged = merge_files(&files, &MergeOptions::default()).unwrap();
+        assert_eq!(merged.questions[0].decimals, 2);
+
+        let section = merged_section("Anketa o izvajalcu", &merged.questions);
+        let row = &section.rows[0];
+        assert_eq!(row.mean, 4.67);
+        assert_eq!(crate::placeholders::format_number("MEAN", row.mean, row.decimals, None).unwrap(), "4.67");
     }
-    Ok(())
 }
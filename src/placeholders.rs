@@ -0,0 +1,266 @@
+//! Placeholders of the responses and the template.
+//!
+//! A placeholder is written as ``{NAME}`` or ``{NAME:argument}``, where the name consists of uppercase
+//! letters, digits and underscores (starting with a letter). Placeholders with an unknown name
+//! are left unchanged, so that LaTeX groups such as ``{AUTO_GEN}`` or ``\textbf{A}`` are not affected.
+//!
+//! Substituted values are escaped for the document they are inserted into (see [`Escape`]), so that
+//! e.g. ``O'Brien & Co`` does not break the compilation. The ``raw`` argument (``{CANDIDATE:raw}``,
+//! ``{MEAN:1,raw}``) inserts the value as it is, e.g. when the value itself is LaTeX markup.
+
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+use crate::markup::{self, Markup};
+
+
+/// Mean grade of the question. ``{MEAN:n}`` rounds the mean to ``n`` decimals.
+pub const MEAN: &str = "MEAN";
+/// Standard deviation of the question. ``{STD:n}`` rounds it to ``n`` decimals.
+pub const STD: &str = "STD";
+/// Number of respondents of the question (``Število odgovorov``).
+pub const COUNT: &str = "COUNT";
+/// Text of the question.
+pub const QUESTION: &str = "QUESTION";
+/// Title of the STUDIS section.
+pub const SECTION: &str = "SECTION";
+/// Location of the generated responses in the template.
+pub const AUTO_GEN: &str = "AUTO_GEN";
+
+/// Argument that disables the escaping of the placeholder's value.
+pub const RAW: &str = "raw";
+
+/// Names of the built-in placeholders, which cannot be used as user variables.
+pub const RESERVED: [&str; 6] = [MEAN, STD, COUNT, QUESTION, SECTION, AUTO_GEN];
+
+
+/// User variables (e.g., ``CANDIDATE``), mapping the placeholder's name to its value.
+pub type Variables = BTreeMap<String, String>;
+
+
+/// Escaping of the substituted values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    /// Values are inserted as they are (e.g., into DOCX text, which is escaped when written).
+    None,
+    /// LaTeX special characters are escaped, in text or math mode, depending on the placeholder's location.
+    Latex,
+    /// Special characters of the markup language are escaped.
+    Markup(Markup)
+}
+
+
+/// Checks whether ``name`` is a valid placeholder name (``[A-Z][A-Z0-9_]*``).
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+
+/// Parses a user variable, given as ``NAME=value``.
+pub fn parse_variable(text: &str) -> Result<(String, String)> {
+    let (name, value) = text.split_once('=')
+        .ok_or_else(|| Error::Input(format!("variable \"{text}\" must be given as NAME=value")))?;
+    let name = name.trim();
+    validate_variable_name(name)?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+
+/// Checks that ``name`` is a valid name of a user variable, which is not reserved for a built-in placeholder.
+pub fn validate_variable_name(name: &str) -> Result<()> {
+    if !is_valid_name(name) {
+        return Err(Error::Input(format!(
+            "invalid variable name \"{name}\" (use uppercase letters, digits and underscores, e.g., CANDIDATE)"
+        )));
+    }
+    if RESERVED.contains(&name) {
+        return Err(Error::Input(format!("variable name \"{name}\" is reserved for a built-in placeholder")));
+    }
+
+    Ok(())
+}
+
+
+/// Replaces the placeholders of ``text`` with the values returned by ``value``, which is given the
+/// placeholder's name and argument. Placeholders for which ``value`` returns ``None`` are left unchanged.
+pub fn substitute(
+    text: &str,
+    mut value: impl FnMut(&str, Option<&str>) -> Result<Option<String>>
+) -> Result<String> {
+    substitute_at(text, |name, arg, _| value(name, arg))
+}
+
+
+/// Same as [`substitute`], but the values are escaped according to ``escape``, unless the placeholder
+/// has the [`RAW`] argument. The ``raw`` argument is removed before ``value`` is called
+/// (e.g., ``{MEAN:1,raw}`` is given the argument ``1``).
+pub fn substitute_escaped(
+    text: &str,
+    escape: Escape,
+    mut value: impl FnMut(&str, Option<&str>) -> Result<Option<String>>
+) -> Result<String> {
+    substitute_at(text, |name, arg, preceding| {
+        let (arg, raw) = split_raw(arg);
+        Ok(value(name, arg)?.map(|v| match escape {
+            _ if raw => v,
+            Escape::None => v,
+            Escape::Latex => escape_latex(&v, in_math(preceding)),
+            Escape::Markup(markup) => markup::escape(&v, markup)
+        }))
+    })
+}
+
+
+/// Splits the [`RAW`] argument from the placeholder's argument ``arg``.
+/// Returns the remaining argument and whether ``raw`` was given.
+pub fn split_raw(arg: Option<&str>) -> (Option<&str>, bool) {
+    match arg {
+        Some(RAW) => (None, true),
+        Some(arg) => match arg.strip_suffix(RAW).and_then(|a| a.strip_suffix(',')) {
+            Some(arg) => (Some(arg), true),
+            None => (Some(arg), false)
+        },
+        None => (None, false)
+    }
+}
+
+
+/// Replaces the placeholders of ``text``, like [`substitute`]. ``value`` is also given the text preceding the placeholder.
+pub(crate) fn substitute_at(
+    text: &str,
+    mut value: impl FnMut(&str, Option<&str>, &str) -> Result<Option<String>>
+) -> Result<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let replacement = match rest[1..].find(['{', '}', '\n']) {
+            Some(end) if rest[1 + end..].starts_with('}') => {
+                let inner = &rest[1..1 + end];
+                let (name, arg) = match inner.split_once(':') {
+                    Some((name, arg)) => (name, Some(arg)),
+                    None => (inner, None)
+                };
+                if is_valid_name(name) {
+                    value(name, arg, &text[..text.len() - rest.len()])?.map(|v| (v, end + 2))
+                }
+                else {
+                    None
+                }
+            }
+            _ => None
+        };
+
+        match replacement {
+            Some((replacement, len)) => {
+                output.push_str(&replacement);
+                rest = &rest[len..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+
+/// Checks whether the end of the LaTeX ``text`` is in (inline) math mode, i.e., after an odd number of unescaped ``$``.
+/// The ``$`` of comments are ignored.
+fn in_math(text: &str) -> bool {
+    let mut math = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '%' => { chars.by_ref().find(|c| *c == '\n'); },
+            '$' => math = !math,
+            _ => {}
+        }
+    }
+    math
+}
+
+
+/// Escapes the LaTeX special characters of ``text``, for text or ``math`` mode.
+fn escape_latex(text: &str, math: bool) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match (c, math) {
+            ('&' | '%' | '$' | '#' | '_' | '{' | '}', _) => {
+                output.push('\\');
+                output.push(c);
+            }
+            ('\\', false) => output += "\\textbackslash{}",
+            ('~', false) => output += "\\textasciitilde{}",
+            ('^', false) => output += "\\textasciicircum{}",
+            ('<', false) => output += "\\textless{}",
+            ('>', false) => output += "\\textgreater{}",
+            ('\\', true) => output += "\\backslash{}",
+            ('~', true) => output += "\\sim{}",
+            ('^', true) => output += "\\mbox{\\textasciicircum{}}",
+            _ => output.push(c)
+        }
+    }
+    output
+}
+
+
+/// Formats the number ``value`` for a placeholder with the argument ``arg``.
+/// Without an argument, ``value`` is written with as many ``decimals`` as in the STUDIS export
+/// (see [`crate::preproc::QuestionRow::decimals`]), otherwise it is rounded to ``arg`` decimals.
+pub fn format_number(name: &str, value: f64, decimals: usize, arg: Option<&str>) -> Result<String> {
+    let Some(arg) = arg else {
+        return Ok(format!("{value:.decimals$}"));
+    };
+
+    let decimals: usize = arg.trim().parse().map_err(|_| Error::Responses(format!(
+        "invalid number of decimals in {{{name}:{arg}}} (expected e.g. {{{name}:1}})"
+    )))?;
+    Ok(format!("{value:.decimals$}"))
+}
+
+
+/// Returns the value of the user variable ``name``, which does not accept an argument.
+pub fn variable(variables: &Variables, name: &str, arg: Option<&str>) -> Result<Option<String>> {
+    match (variables.get(name), arg) {
+        (Some(_), Some(arg)) => Err(Error::Template(format!("variable {{{name}:{arg}}} does not accept an argument"))),
+        (value, _) => Ok(value.cloned())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_math() {
+        assert!(in_math("mean $x = "));
+        assert!(!in_math("costs \\$5, mean "));
+        assert!(!in_math("% a $ in a comment\nmean "));
+        assert!(in_math("% a $ in a comment\n$x = "));
+    }
+
+    #[test]
+    fn formats_numbers() {
+        assert_eq!(format_number(MEAN, 4.5, 2, None).unwrap(), "4.50");
+        assert_eq!(format_number(MEAN, 5.0, 2, None).unwrap(), "5.00");
+        assert_eq!(format_number(MEAN, 4.5, 2, Some("1")).unwrap(), "4.5");
+        assert!(format_number(MEAN, 4.5, 2, Some("x")).is_err());
+    }
+
+    #[test]
+    fn escapes_latex() {
+        assert_eq!(escape_latex("a^b~c", false), "a\\textasciicircum{}b\\textasciitilde{}c");
+        assert_eq!(escape_latex("a^b~c", true), "a\\mbox{\\textasciicircum{}}b\\sim{}c");
+        assert_eq!(escape_latex("50% & #1", false), "50\\% \\& \\#1");
+    }
+}
@@ -1,11 +1,24 @@
-use std::collections::HashMap;
-use anyhow::{Context, Result, anyhow};
-use csv;
+//! Preprocessing of the surveys exported from STUDIS, as CSV files or spreadsheets (XLSX, XLS or ODS).
+
+use calamine::Reader;
+use encoding_rs::Encoding;
+
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::fs::read_file_encoding;
 
 
 /// Possible delimiters of a CSV file.
 const DELIMITERS: [char; 4] = [',', ';', '\t', ' '];
 
+/// Extensions of the spreadsheet files, which are read as spreadsheets instead of CSV files.
+pub const SPREADSHEET_EXTENSIONS: [&str; 4] = ["xlsx", "xlsm", "xls", "ods"];
+
+/// Extensions of all the supported survey files (for file pickers).
+pub const SURVEY_EXTENSIONS: [&str; 5] = ["csv", "xlsx", "xlsm", "xls", "ods"];
+
 
 /// Finds the delimiter of a CSV file.
 /// If the delimiter cannot be found, the default comma is returned.
@@ -15,9 +28,6 @@ pub fn get_delimiter(content: &str) -> Result<char> {
         Quote
     }
 
-    const MAX_CONTENT_ERR_PRINT: usize = 5000;
-
-
     let mut counts = Vec::new();
     let mut read_state = ReadState::Normal;
 
@@ -64,117 +74,509 @@ pub fn get_delimiter(content: &str) -> Result<char> {
     // from the possible delimiters tables.
     let (index, count) = counts[0].iter().enumerate().max_by_key(|(_, cnt)| **cnt).unwrap();
     if *count == 0 {
-        return Err(anyhow!(
-            "could not detect the CSV delimiter (inconsistent use or not a CSV). Content:\n\n{}",
-            &content[0..MAX_CONTENT_ERR_PRINT.min(content.len())]
-        ));
+        return Err(Error::Survey(format!(
+            "could not detect the CSV delimiter (none of {} is used consistently)",
+            DELIMITERS.map(delimiter_name).join(", ")
+        )));
     }
 
     Ok(DELIMITERS[index])
 }
 
 
-/// Preprocesses a CSV file exported from STUDIS. The result is a mapping, mapping different sections (as keys)
-/// to CSV-compatible tables, which can be parsed by the csv crate.
-pub fn preprocess_candidate_csv(filedata: String) -> HashMap<String, String> {   
-    enum CSVParsingState {
-        Header = 0,
-        Columns
+/// Parses a CSV delimiter, as given by the user: a single ASCII character, ``tab`` or ``space``.
+pub fn parse_delimiter(text: &str) -> Result<char> {
+    match text {
+        "tab" | "\\t" => Ok('\t'),
+        "space" => Ok(' '),
+        _ => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii() && c != '"' && c != '\n' => Ok(c),
+                _ => Err(Error::Input(format!(
+                    "invalid delimiter \"{text}\" (expected a single character, e.g. \";\", or tab)"
+                )))
+            }
+        }
     }
+}
+
+
+/// Returns a readable name of the CSV ``delimiter`` (e.g., ``tab``).
+pub fn delimiter_name(delimiter: char) -> String {
+    match delimiter {
+        '\t' => "tab".to_string(),
+        ' ' => "space".to_string(),
+        c => format!("\"{c}\"")
+    }
+}
+
+
+/// Column of the question texts.
+pub const QUESTION_COLUMN: &str = "Vprašanje";
+/// Column of the numbers of respondents.
+pub const COUNT_COLUMN: &str = "Število odgovorov";
+/// Column of the mean grades.
+pub const MEAN_COLUMN: &str = "Povprečje";
+/// Column of the standard deviations of the grades.
+pub const STD_COLUMN: &str = "Standardni odklon";
+
+
+/// A survey exported from STUDIS as a CSV file or a spreadsheet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SurveyExport {
+    /// The sections of the survey, in the order of the file.
+    pub sections: Vec<Section>
+}
+
+
+/// A section of the survey (e.g., ``Anketa o izvajalcu``).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Section {
+    pub title: String,
+    /// The questions of the section, in the order of the file.
+    pub rows: Vec<QuestionRow>
+}
+
+
+/// The grades of a single question.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuestionRow {
+    /// Text of the question.
+    pub question: String,
+    /// Number of respondents. ``None`` if the section has no ``Število odgovorov`` column (or the cell is empty).
+    pub count: Option<u64>,
+    /// Mean grade.
+    pub mean: f64,
+    /// Standard deviation of the grades. ``None`` if the section has no ``Standardni odklon`` column
+    /// (or the cell is empty).
+    pub std: Option<f64>,
+    /// Number of decimals of the mean and the standard deviation in the STUDIS export (the larger of the two),
+    /// with which they are written into the report (see [`crate::placeholders::format_number`]).
+    pub decimals: usize
+}
+
+
+/// Options of reading a STUDIS export. The options that are not given are detected from the file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SurveyOptions {
+    /// Encoding of a CSV file (see [`read_file_encoding`]).
+    pub encoding: Option<&'static Encoding>,
+    /// Delimiter of a CSV file (see [`get_delimiter`]).
+    pub delimiter: Option<char>,
+    /// Decimal separator of the numbers (see [`DecimalSeparator::detect`]).
+    pub decimal: Option<DecimalSeparator>
+}
+
 
-    /// Replaces the slovenian style float notation (comma separated) with regular float notation.
-    fn fix_floats(lines: &Vec<&str>) -> String {
-        enum NumberState {
-            NaN,
-            Whole,
-            Decimal,
+/// How a section of a STUDIS export was read (see [`SurveyExport::inspect`]).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionLayout {
+    /// Sheet of the section, if read from a spreadsheet.
+    pub sheet: Option<String>,
+    /// Number of the section's title line (or row of the sheet).
+    pub line: usize,
+    /// Delimiter of the section's table, if read from a CSV file.
+    pub delimiter: Option<char>,
+    /// Decimal separator of the section's numbers.
+    pub decimal: DecimalSeparator
+}
+
+
+/// Diagnostics of reading a STUDIS export (see [`SurveyExport::inspect`]).
+#[derive(Debug)]
+pub struct Inspection {
+    /// Encoding of the CSV file. ``None`` for spreadsheets.
+    pub encoding: Option<&'static Encoding>,
+    /// The read sections with their layouts, or the error of reading them.
+    pub sections: Result<Vec<(Section, SectionLayout)>>
+}
+
+
+/// Decimal separator of the numbers in a STUDIS export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecimalSeparator {
+    /// Slovenian notation (``4,27``), used by STUDIS.
+    Comma,
+    /// English notation (``4.27``), e.g., after the export was resaved by a spreadsheet program.
+    Point
+}
+
+
+impl DecimalSeparator {
+    /// Returns the separator's character.
+    pub fn char(self) -> char {
+        match self {
+            Self::Comma => ',',
+            Self::Point => '.'
         }
-        let mut state = NumberState::NaN;
-        let mut olines = Vec::with_capacity(lines.len());
-        let mut chars;
-        let mut sline;
-
-        for line in lines {
-            chars = line.chars();
-            sline = String::with_capacity(line.len());
-            for char in chars {
-                sline.push(match &state {
-                    NumberState::NaN => {
-                        if char.is_numeric() {
-                            state = NumberState::Whole;
-                        }
-                        char
-                    },
-                    NumberState::Whole => {
-                        if char == ',' || char == '.' {
-                            state = NumberState::Decimal;
-                            '.'
-                        }
-                        else {
-                            char
-                        }
-                    }
-                    NumberState::Decimal => {
-                        if !char.is_numeric() {
-                            state = NumberState::NaN
-                        }
-                        char
-                    }
-                });
-            }
+    }
 
-            olines.push(sline);
+    /// Detects the decimal separator from the ``numbers`` (cells of a decimal column),
+    /// as the first separator found in them. Returns ``None`` if all the numbers are whole.
+    pub fn detect<'a>(numbers: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        numbers.into_iter()
+            .flat_map(str::chars)
+            .find_map(|c| [Self::Comma, Self::Point].into_iter().find(|s| s.char() == c))
+    }
+
+    /// Parses the decimal ``number`` written with this separator.
+    /// The other separator is not accepted, since it would be a thousands separator.
+    pub fn parse(self, number: &str) -> Option<f64> {
+        match self {
+            Self::Comma if number.contains('.') => None,
+            Self::Comma => number.replacen(',', ".", 1).parse().ok(),
+            Self::Point => number.parse().ok()
         }
-        olines.join("\n")
     }
+}
 
-    let mut map = HashMap::new();
-    let mut state = CSVParsingState::Header;
-    let mut key= String::new();
-    let mut key_data = Vec::new();
 
-    for line in filedata.lines() {
-        match state {
-            CSVParsingState::Header => {
-                key = String::from(line.trim_matches(DELIMITERS).trim());
-                key_data.clear();
-                state = CSVParsingState::Columns;
-            }
-            CSVParsingState::Columns => {
-                if line.trim_matches(DELIMITERS).trim() == "" {  // Empty line
-                    map.insert(key.clone(), fix_floats(&key_data));
-                    state = CSVParsingState::Header;
-                }
-                key_data.push(line);
+impl SurveyExport {
+    /// Reads the STUDIS export at ``filepath`` with the ``options`` (see [`SurveyExport::inspect`]).
+    pub fn from_file(filepath: &Path, options: &SurveyOptions) -> Result<Self> {
+        let sections = Self::inspect(filepath, options)?.sections?;
+        Ok(Self { sections: sections.into_iter().map(|(section, _)| section).collect() })
+    }
+
+    /// Reads the STUDIS export at ``filepath`` and returns how it was read, for diagnosing odd exports.
+    /// Files with one of the [`SPREADSHEET_EXTENSIONS`] are read as spreadsheets (see [`SurveyExport::from_spreadsheet`]),
+    /// other files as CSV (see [`read_file_encoding`] and [`SurveyExport::parse`]).
+    pub fn inspect(filepath: &Path, options: &SurveyOptions) -> Result<Inspection> {
+        let (encoding, sections) = if is_spreadsheet(filepath) {
+            (None, read_spreadsheet(filepath, options))
+        }
+        else {
+            let (fdata, encoding) = read_file_encoding(filepath, options.encoding)
+                .map_err(|e| Error::io("unable to read STUDIS CSV", filepath, e))?;
+            (Some(encoding), read_csv(&fdata, options))
+        };
+
+        let sections = sections.map_err(|e| match e {
+            Error::Survey(message) => Error::Survey(format!("{}, {message}", filepath.display())),
+            e => e
+        });
+        Ok(Inspection { encoding, sections })
+    }
+
+    /// Parses the content of a STUDIS CSV export. The sections are separated by empty lines. Each section starts
+    /// with its title, followed by a table of questions with a header row. Errors contain the number of the
+    /// malformed line. The delimiter and the decimal separator are detected for each section
+    /// (see [`get_delimiter`] and [`DecimalSeparator::detect`]).
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::parse_with(content, &SurveyOptions::default())
+    }
+
+    /// Same as [`SurveyExport::parse`], but with the delimiter and the decimal separator of the ``options``
+    /// (detected when not given).
+    pub fn parse_with(content: &str, options: &SurveyOptions) -> Result<Self> {
+        let sections = read_csv(content, options)?;
+        Ok(Self { sections: sections.into_iter().map(|(section, _)| section).collect() })
+    }
+
+    /// Reads a STUDIS export saved as a spreadsheet (XLSX, XLS or ODS) at ``filepath``. The sheets are read in order,
+    /// each with the same layout as the CSV file (see [`SurveyExport::parse`]): sections separated by empty rows,
+    /// each starting with a row containing its title. Numbers stored as text are parsed with the decimal
+    /// separator of the ``options`` (detected for each section when not given).
+    pub fn from_spreadsheet(filepath: &Path, options: &SurveyOptions) -> Result<Self> {
+        let sections = read_spreadsheet(filepath, options)?;
+        Ok(Self { sections: sections.into_iter().map(|(section, _)| section).collect() })
+    }
+
+    /// Returns the section with the ``title``.
+    pub fn section(&self, title: &str) -> Result<&Section> {
+        self.sections.iter().find(|s| s.title == title).ok_or_else(|| Error::Survey(format!(
+            "could not find section \"{title}\" in STUDIS export ({:?})",
+            self.sections.iter().map(|s| s.title.as_str()).collect::<Vec<_>>()
+        )))
+    }
+}
+
+
+impl Section {
+    /// Parses the table of the section ``title``, whose ``lines`` start at line ``first_line`` of the file.
+    /// The delimiter and the decimal separator of the ``options`` are detected from the section when not given.
+    fn parse(title: String, lines: &[&str], first_line: usize, options: &SurveyOptions) -> Result<(Self, SectionLayout)> {
+        let error = |line: usize, message: String| Error::Survey(format!("line {line}: {message}"));
+        if lines.is_empty() {
+            return Err(error(first_line - 1, format!("section \"{title}\" has no table of questions")));
+        }
+
+        // A malformed row makes the delimiter inconsistent, so the header alone is tried as well.
+        // The malformed row is then reported by the CSV reader.
+        let table = lines.join("\n");
+        let delimiter = match options.delimiter {
+            Some(delimiter) => delimiter,
+            None => get_delimiter(&table).or_else(|_| get_delimiter(lines[0])).map_err(|_| error(
+                first_line, format!("could not detect the CSV delimiter of section \"{title}\" (it can be given explicitly)")
+            ))?
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter as u8)
+            .from_reader(table.as_bytes());
+
+        // Line of the file, from the line of the section's table
+        let file_line = |position: Option<&csv::Position>| first_line + position.map_or(1, |p| p.line() as usize) - 1;
+        let csv_error = |e: csv::Error| {
+            let line = file_line(e.position());
+            match e.kind() {
+                csv::ErrorKind::UnequalLengths { expected_len, len, .. } => error(
+                    line, format!("expected {expected_len} fields (as in the header), found {len}")
+                ),
+                _ => error(line, e.to_string())
             }
+        };
+
+        let headers = reader.headers().map_err(csv_error)?.clone();
+        let records = reader.records().collect::<csv::Result<Vec<_>>>().map_err(csv_error)?;
+        let table: Vec<(usize, Vec<&str>)> = std::iter::once((first_line, headers.iter().collect()))
+            .chain(records.iter().map(|r| (file_line(r.position()), r.iter().collect())))
+            .collect();
+        let (section, decimal) = Self::from_table(title, &table, first_line, options.decimal, "line")?;
+        Ok((section, SectionLayout { sheet: None, line: first_line - 1, delimiter: Some(delimiter), decimal }))
+    }
+
+    /// Reads the section ``title`` from its ``table`` (the header, followed by the questions), with the rows' numbers
+    /// in the file. ``first_line`` is the number of the header's row, used when the table is empty.
+    /// The numbers are parsed with the ``decimal`` separator (detected from the section if ``None``).
+    /// Errors are reported at the ``location`` (e.g., ``line``), followed by a row's number.
+    /// Returns the section and the used decimal separator.
+    fn from_table(
+        title: String,
+        table: &[(usize, Vec<&str>)],
+        first_line: usize,
+        decimal: Option<DecimalSeparator>,
+        location: &str
+    ) -> Result<(Self, DecimalSeparator)> {
+        let error = |line: usize, message: String| Error::Survey(format!("{location} {line}: {message}"));
+        let Some(((_, headers), records)) = table.split_first() else {
+            return Err(error(first_line - 1, format!("section \"{title}\" has no table of questions")));
+        };
+
+        let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+        let required = |name: &str| column(name).ok_or_else(|| error(
+            first_line, format!("section \"{title}\" is missing the \"{name}\" column")
+        ));
+        let (question_column, mean_column) = (required(QUESTION_COLUMN)?, required(MEAN_COLUMN)?);
+        let (count_column, std_column) = (column(COUNT_COLUMN), column(STD_COLUMN));
+
+        let decimal = decimal.or_else(|| DecimalSeparator::detect(
+            records.iter().flat_map(|(_, r)| [Some(mean_column), std_column].into_iter().flatten().filter_map(|c| r.get(c).copied()))
+        )).unwrap_or(DecimalSeparator::Point);
+
+        let mut rows = Vec::new();
+        for (line, record) in records {
+            let line = *line;
+            let cell = |column: usize| record.get(column).copied().unwrap_or_default().trim();
+            let optional_cell = |column: Option<usize>| column.map(cell).filter(|c| !c.is_empty());
+            let invalid = |what: &str, value: &str| error(line, format!("could not parse the {what} \"{value}\""));
+
+            let mean = cell(mean_column);
+            let decimals = [Some(mean), optional_cell(std_column)].into_iter().flatten()
+                .map(|c| c.split_once(decimal.char()).map_or(0, |(_, fraction)| fraction.len()))
+                .max()
+                .unwrap_or_default();
+            rows.push(QuestionRow {
+                question: cell(question_column).to_string(),
+                count: optional_cell(count_column)
+                    .map(|c| c.parse().map_err(|_| invalid("respondent count", c)))
+                    .transpose()?,
+                mean: decimal.parse(mean).ok_or_else(|| invalid("mean", mean))?,
+                std: optional_cell(std_column)
+                    .map(|c| decimal.parse(c).ok_or_else(|| invalid("standard deviation", c)))
+                    .transpose()?,
+                decimals
+            });
         }
+
+        Ok((Self { title, rows }, decimal))
     }
 
-    if let CSVParsingState::Columns = state {  // In case there was no new empty line
-        map.insert(key.clone(), fix_floats(&key_data));
+    /// Returns the texts of the section's questions.
+    pub fn questions(&self) -> Vec<String> {
+        self.rows.iter().map(|row| row.question.clone()).collect()
     }
+}
 
-    map
+
+/// Checks whether the file at ``filepath`` is a spreadsheet (see [`SPREADSHEET_EXTENSIONS`]).
+pub fn is_spreadsheet(filepath: &Path) -> bool {
+    filepath.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SPREADSHEET_EXTENSIONS.iter().any(|s| s.eq_ignore_ascii_case(e)))
 }
 
 
-pub fn extract_section_columns(sections: HashMap<String, String>, section: &str) -> Result<HashMap<String, Vec<String>>> {
-    let csvgrades: &String = sections.get(section)
-        .with_context(|| format!("could not find key \"{section}\" in CSV STUDIS file ({:?})", sections.keys()))?;
+/// Reads the sections of a STUDIS CSV export (see [`SurveyExport::parse`]) with their layouts.
+fn read_csv(content: &str, options: &SurveyOptions) -> Result<Vec<(Section, SectionLayout)>> {
+    let lines: Vec<&str> = content.lines().collect();
+    let is_empty = |line: &&str| line.trim_matches(|c| DELIMITERS.contains(&c) || Some(c) == options.delimiter)
+        .trim()
+        .is_empty();
+
+    split_sections(&lines, is_empty)
+        .map(|(title, table)| {
+            let title = lines[title].trim_matches(|c| DELIMITERS.contains(&c) || Some(c) == options.delimiter).trim();
+            Section::parse(title.to_string(), &lines[table.clone()], table.start + 1, options)
+        })
+        .collect()
+}
+
 
-    let delimiter = get_delimiter(&csvgrades)?;
-    let mut csvgrades = csv::ReaderBuilder::new()
-        .delimiter(delimiter as u8)
-        .from_reader(csvgrades.as_bytes());
+/// Reads the sections of a STUDIS spreadsheet (see [`SurveyExport::from_spreadsheet`]) with their layouts.
+fn read_spreadsheet(filepath: &Path, options: &SurveyOptions) -> Result<Vec<(Section, SectionLayout)>> {
+    let mut workbook = calamine::open_workbook_auto(filepath).map_err(|e| match e {
+        calamine::Error::Io(e) => Error::io("unable to read STUDIS spreadsheet", filepath, e),
+        e => Error::Survey(format!("could not read the spreadsheet: {e}"))
+    })?;
 
-    /* Transpose the CSV. That is, return a mapping of Header -> Vec<Vertical data> */
-    let headers: Vec<String> = csvgrades.headers()?.iter().map(|h| h.to_string()).collect();
-    let mut column_map: HashMap<String, Vec<String>> = HashMap::from_iter(headers.iter().map(|h| (h.clone(), Vec::new())));
-    for record in csvgrades.records() {
-        let record = record?;
-        for (header, column) in headers.iter().zip(record.iter()) {
-            column_map.get_mut(header).unwrap().push(column.to_string());
+    let mut sections = Vec::new();
+    for sheet in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&sheet)
+            .map_err(|e| Error::Survey(format!("could not read sheet \"{sheet}\": {e}")))?;
+
+        // Rows are numbered as in the spreadsheet program
+        let first_row = range.start().map_or(1, |(row, _)| row as usize + 1);
+        let rows: Vec<(usize, Vec<String>)> = range.rows().enumerate()
+            .map(|(i, row)| (first_row + i, row.iter().map(|cell| cell.to_string().trim().to_string()).collect()))
+            .collect();
+        let location = format!("sheet \"{sheet}\", row");
+
+        for (title, table) in split_sections(&rows, |(_, cells)| cells.iter().all(String::is_empty)) {
+            let (title_row, cells) = &rows[title];
+            let title = cells.iter().find(|c| !c.is_empty()).cloned().unwrap_or_default();
+            let table: Vec<_> = rows[table].iter()
+                .map(|(row, cells)| (*row, cells.iter().map(String::as_str).collect()))
+                .collect();
+            let (section, decimal) = Section::from_table(title, &table, title_row + 1, options.decimal, &location)?;
+            let layout = SectionLayout { sheet: Some(sheet.clone()), line: *title_row, delimiter: None, decimal };
+            sections.push((section, layout));
         }
     }
-    Ok(column_map)
+
+    Ok(sections)
+}
+
+
+/// Splits the ``rows`` of a STUDIS export into sections, separated by the rows that are ``empty``.
+/// Returns the index of each section's title row and the range of its table.
+fn split_sections<T>(rows: &[T], empty: impl Fn(&T) -> bool) -> impl Iterator<Item = (usize, Range<usize>)> {
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        i += rows[i..].iter().position(|r| !empty(r))?;
+        let end = rows[i + 1..].iter().position(&empty).map_or(rows.len(), |p| i + 1 + p);
+        let section = (i, i + 1..end);
+        i = end;
+        Some(section)
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Section of a STUDIS export, as in ``anketa.csv`` (tab delimited, with decimal commas).
+    const SECTION: &str = "Anketa o izvajalcu\t\t\t
+Vprašanje\tŠtevilo odgovorov\tPovprečje\tStandardni odklon
+Gledano v celoti, je delo izvajalca/ke kakovostno.\t30\t4,83\t0,46
+V predavalnico prihaja dobro pripravljen/a.\t30\t4,9\t0,4
+";
+
+    #[test]
+    fn parses_anketa() {
+        let survey = SurveyExport::from_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("anketa.csv"), &SurveyOptions::default()).unwrap();
+        let titles: Vec<_> = survey.sections.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, [
+            "Anketa o predmetu", "Anketa o izvajalcu", "Anketa o predmetu po zaključnem preverjanju znanja"
+        ]);
+
+        let section = survey.section("Anketa o predmetu").unwrap();
+        assert_eq!(section.rows.len(), 7);
+        assert_eq!(section.rows[0], QuestionRow {
+            question: "Gledano v celoti, sem s predmetom zadovoljen/a.".to_string(),
+            count: Some(33),
+            mean: 4.27,
+            std: Some(0.91),
+            decimals: 2
+        });
+
+        // Commas following numbers in the question texts are kept
+        let last = survey.sections[2].rows.last().unwrap();
+        assert!(last.question.contains("(1 ECTS = 25-30 ur obremenitve, ki vključuje predavanja, vaje, seminar itd."));
+        assert_eq!((last.count, last.mean, last.std), (Some(15), 3.33, Some(0.62)));
+    }
+
+    #[test]
+    fn detects_decimal_separator() {
+        let comma = SurveyExport::parse(SECTION).unwrap();
+        assert_eq!(comma.sections[0].rows[1].mean, 4.9);
+
+        let point = SurveyExport::parse(&SECTION.replace("4,83", "4.83").replace("0,46", "0.46")
+            .replace("4,9", "4.9").replace("0,4", "0.4")).unwrap();
+        assert_eq!(point, comma);
+
+        assert_eq!(DecimalSeparator::detect(["5", "4"]), None);
+        assert_eq!(DecimalSeparator::detect(["5", "4,5"]), Some(DecimalSeparator::Comma));
+    }
+
+    #[test]
+    fn keeps_decimals() {
+        let section = &SurveyExport::parse(&SECTION.replace("4,83", "4,50")).unwrap().sections[0];
+        assert_eq!((section.rows[0].mean, section.rows[0].decimals), (4.5, 2));
+        assert_eq!((section.rows[1].mean, section.rows[1].decimals), (4.9, 1));
+    }
+
+    #[test]
+    fn parses_comma_delimited() {
+        let content = "Anketa o izvajalcu,,,
+Vprašanje,Število odgovorov,Povprečje,Standardni odklon
+\"Snov podaja na razumljiv način, 1, 2, 3.\",30,\"4,7\",\"0,65\"
+";
+        let row = &SurveyExport::parse(content).unwrap().sections[0].rows[0];
+        assert_eq!(row.question, "Snov podaja na razumljiv način, 1, 2, 3.");
+        assert_eq!((row.count, row.mean, row.std), (Some(30), 4.7, Some(0.65)));
+    }
+
+    #[test]
+    fn explicit_decimal_separator() {
+        let comma = SurveyOptions { decimal: Some(DecimalSeparator::Comma), ..Default::default() };
+        let survey = SurveyExport::parse_with(SECTION, &comma).unwrap();
+        assert_eq!(survey.sections[0].rows[0].mean, 4.83);
+
+        let point = SurveyOptions { decimal: Some(DecimalSeparator::Point), ..Default::default() };
+        let error = SurveyExport::parse_with(SECTION, &point).unwrap_err();
+        assert!(error.to_string().contains("line 3: could not parse the mean \"4,83\""), "{error}");
+    }
+
+    #[test]
+    fn explicit_delimiter() {
+        // The pipe is not one of the detected delimiters
+        let content = "Anketa o izvajalcu|||
+Vprašanje|Število odgovorov|Povprečje|Standardni odklon
+Snov podaja jasno.|30|4,7|0,65
+|||
+Anketa o predmetu
+Vprašanje|Število odgovorov|Povprečje|Standardni odklon
+Predmet je dober.|12|4|1
+";
+        let options = SurveyOptions { delimiter: Some(parse_delimiter("|").unwrap()), ..Default::default() };
+        let survey = SurveyExport::parse_with(content, &options).unwrap();
+        assert_eq!(survey.sections.len(), 2);
+        assert_eq!(survey.sections[0].rows[0].mean, 4.7);
+        assert_eq!(survey.section("Anketa o predmetu").unwrap().rows[0].std, Some(1.0));
+
+        assert_eq!(parse_delimiter("tab").unwrap(), '\t');
+        assert!(parse_delimiter(";;").is_err());
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let error = SurveyExport::parse(&SECTION.replace("\t4,9\t", "\tabc\t")).unwrap_err();
+        assert!(error.to_string().contains("line 4: could not parse the mean \"abc\""), "{error}");
+
+        let error = SurveyExport::parse(&SECTION.replace("\t0,46", "\t0,46\t1")).unwrap_err();
+        assert!(error.to_string().contains("line 3: expected 4 fields (as in the header), found 5"), "{error}");
+    }
 }
@@ -0,0 +1,661 @@
+//! Model of the JSON responses file.
+//!
+//! The file maps each STUDIS question to grade thresholds and each threshold to the possible responses:
+//! ```json
+//! {
+//!     "Vprašanje": {
+//!         "Gledano v celoti, je delo izvajalca/ke kakovostno.": {
+//!             "4.5": ["Odziv 1", "Odziv 2"],
+//!             "1": ["Odziv 3"]
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Questions of other STUDIS sections are given under the section's title:
+//! ```json
+//! {
+//!     "Vprašanje": { ... },
+//!     "Anketa o predmetu": {
+//!         "Vprašanje": {
+//!             "Gledano v celoti, sem s predmetom zadovoljen/a.": { ... }
+//!         }
+//!     }
+//! }
+//! ```
+//! The top-level ``"Vprašanje"`` mapping belongs to the default section (chosen when generating the report).
+//!
+//! Besides grade thresholds, a question can have buckets with an explicit rule on the mean, the standard deviation
+//! and the number of respondents (``min`` is inclusive, ``max`` is exclusive):
+//! ```json
+//! "Gledano v celoti, je delo izvajalca/ke kakovostno.": {
+//!     "malo odgovorov": {"rule": {"count": {"max": 5}}, "responses": ["Odziv 1"]},
+//!     "enotno odlično": {"rule": {"mean": {"min": 4.5}, "std": {"max": 0.5}}, "responses": ["Odziv 2"]},
+//!     "4": ["Odziv 3"],
+//!     "1": ["Odziv 4"]
+//! }
+//! ```
+//! Rule buckets are evaluated in order of the file, before the grade thresholds. The first matching bucket is used.
+//! The grade thresholds and the rules on the mean alone must cover all the means from 1 to 5.
+//!
+//! Other texts of a question (e.g., from older STUDIS exports) can be listed under the ``"aliases"`` key of the question
+//! (see [`crate::matching`]).
+
+use serde_json as sj;
+
+use std::path::Path;
+use std::fmt;
+use std::fs::File;
+
+use crate::error::{Error, Result};
+use crate::preproc::QuestionRow;
+
+
+/// Key of the JSON mapping that contains the questions.
+pub const QUESTIONS_KEY: &str = "Vprašanje";
+/// Key of a bucket's rule.
+pub const RULE_KEY: &str = "rule";
+/// Key of a bucket's responses.
+pub const RESPONSES_KEY: &str = "responses";
+/// Key of a question's aliases.
+pub const ALIASES_KEY: &str = "aliases";
+
+const RULE_MEAN_KEY: &str = "mean";
+const RULE_STD_KEY: &str = "std";
+const RULE_COUNT_KEY: &str = "count";
+const RANGE_MIN_KEY: &str = "min";
+const RANGE_MAX_KEY: &str = "max";
+
+/// The lowest STUDIS grade.
+const GRADE_MIN: f64 = 1.0;
+/// The highest STUDIS grade.
+const GRADE_MAX: f64 = 5.0;
+
+
+/// A validated set of responses.
+#[derive(Clone, Debug)]
+pub struct ResponseSet {
+    /// Responses of each STUDIS section.
+    pub sections: Vec<SectionResponses>
+}
+
+
+/// Responses of a single STUDIS section.
+#[derive(Clone, Debug)]
+pub struct SectionResponses {
+    /// Title of the STUDIS section (e.g., "Anketa o predmetu").
+    /// ``None`` for the top-level questions, which belong to the default section.
+    pub title: Option<String>,
+    /// Questions in the order of the JSON file.
+    pub questions: Vec<Question>
+}
+
+
+/// Responses of a single STUDIS question.
+#[derive(Clone, Debug)]
+pub struct Question {
+    /// Text of the question, as written in the STUDIS export.
+    pub text: String,
+    /// Other texts of the question (e.g., from older STUDIS exports), used when matching it to the export.
+    pub aliases: Vec<String>,
+    /// Buckets of responses, in order of evaluation: the rule buckets in order of the JSON file,
+    /// followed by the grade thresholds from the largest to the lowest minimum mean.
+    pub buckets: Vec<Bucket>
+}
+
+
+/// Responses that apply when the question's grades match the ``rule``.
+#[derive(Clone, Debug)]
+pub struct Bucket {
+    /// Key of the bucket in the JSON file (the grade threshold or the name of a rule bucket).
+    pub key: String,
+    /// JSON path of the bucket's responses (see [`Issue::path`]).
+    pub path: String,
+    /// Condition for the bucket's responses.
+    pub rule: Rule,
+    /// The possible responses. Never empty.
+    pub responses: Vec<String>
+}
+
+
+/// Condition on the grades of a question. All the given ranges must match.
+#[derive(Clone, Debug, Default)]
+pub struct Rule {
+    /// Range of the mean grade.
+    pub mean: Range,
+    /// Range of the standard deviation.
+    pub std: Range,
+    /// Range of the number of respondents.
+    pub count: Range
+}
+
+
+/// A range of values. ``min`` is inclusive, ``max`` is exclusive and both are optional.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Range {
+    pub min: Option<f64>,
+    pub max: Option<f64>
+}
+
+
+/// A problem found while validating the responses file (or while linting it, see [`crate::lint`]).
+#[derive(Clone, Debug)]
+pub struct Issue {
+    /// JSON path of the offending value (e.g., ``$["Vprašanje"]["..."]["4.5"][1]``).
+    /// Problems of other files are located by the file's path (e.g., ``mnenje.tex:12``).
+    pub path: String,
+    /// Description of the problem.
+    pub message: String
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+
+impl ResponseSet {
+    /// Reads and validates the responses file at ``filepath``.
+    pub fn from_file(filepath: &Path) -> Result<Self> {
+        let file = File::open(filepath).map_err(|e| Error::io("could not open responses file", filepath, e))?;
+        let value: sj::Value = sj::from_reader(file)?;
+        Self::from_value(&value)
+    }
+
+    /// Validates the parsed JSON ``value`` and converts it into a [`ResponseSet`].
+    /// All problems are reported at once through [`Error::ResponseSchema`].
+    pub fn from_value(value: &sj::Value) -> Result<Self> {
+        let mut issues = Vec::new();
+        let set = Self::parse(value, &mut issues);
+        if issues.is_empty() {
+            Ok(set)
+        }
+        else {
+            Err(Error::ResponseSchema(issues))
+        }
+    }
+
+    /// Converts the ``value`` into a [`ResponseSet`], skipping invalid parts and recording them in ``issues``.
+    pub(crate) fn parse(value: &sj::Value, issues: &mut Vec<Issue>) -> Self {
+        let mut sections = Vec::new();
+        let Some(root) = value.as_object() else {
+            issues.push(Issue { path: "$".to_string(), message: "must be a JSON mapping".to_string() });
+            return Self { sections };
+        };
+
+        if root.is_empty() {
+            issues.push(Issue {
+                path: "$".to_string(),
+                message: format!("has no \"{QUESTIONS_KEY}\" key or STUDIS sections")
+            });
+        }
+
+        for (key, value) in root {
+            if key == QUESTIONS_KEY {
+                sections.push(SectionResponses::parse(None, root, "$", issues));
+            }
+            else {
+                let path = json_path("$", key);
+                match value.as_object() {
+                    Some(section) => sections.push(SectionResponses::parse(Some(key), section, &path, issues)),
+                    None => issues.push(Issue {
+                        path,
+                        message: format!("must be a STUDIS section mapping with the \"{QUESTIONS_KEY}\" key")
+                    })
+                }
+            }
+        }
+
+        Self { sections }
+    }
+
+    /// Returns the responses of the section ``title`` (``None`` means the ``default_section``).
+    /// The top-level questions of the file are used for the ``default_section``, unless it is given explicitly.
+    pub fn section(&self, title: Option<&str>, default_section: &str) -> Option<&SectionResponses> {
+        let title = title.unwrap_or(default_section);
+        self.sections.iter().find(|s| s.title.as_deref() == Some(title))
+            .or_else(|| (title == default_section).then(|| self.sections.iter().find(|s| s.title.is_none())).flatten())
+    }
+}
+
+
+impl SectionResponses {
+    fn parse(title: Option<&str>, section: &sj::Map<String, sj::Value>, path: &str, issues: &mut Vec<Issue>) -> Self {
+        let mut questions = Vec::new();
+        let qpath = json_path(path, QUESTIONS_KEY);
+        match section.get(QUESTIONS_KEY).map(sj::Value::as_object) {
+            None => issues.push(Issue { path: path.to_string(), message: format!("is missing the \"{QUESTIONS_KEY}\" key") }),
+            Some(None) => issues.push(Issue { path: qpath, message: "must be a mapping of question => thresholds".to_string() }),
+            Some(Some(qmap)) => {
+                for (text, grades) in qmap {
+                    let path = json_path(&qpath, text);
+                    if let Some(question) = Question::parse(text, grades, &path, issues) {
+                        questions.push(question);
+                    }
+                }
+            }
+        }
+
+        Self { title: title.map(str::to_string), questions }
+    }
+}
+
+
+impl Question {
+    fn parse(text: &str, value: &sj::Value, path: &str, issues: &mut Vec<Issue>) -> Option<Self> {
+        let Some(grades) = value.as_object() else {
+            issues.push(Issue { path: path.to_string(), message: "must be a mapping of grade => responses".to_string() });
+            return None;
+        };
+
+        if grades.is_empty() {
+            issues.push(Issue { path: path.to_string(), message: "has no grade thresholds".to_string() });
+            return None;
+        }
+
+        let n_issues = issues.len();
+        let mut aliases = Vec::new();
+        let mut rules = Vec::new();
+        let mut thresholds: Vec<(f64, Bucket)> = Vec::with_capacity(grades.len());
+        for (key, bucket) in grades {
+            let gpath = json_path(path, key);
+
+            // Other texts of the question
+            if key == ALIASES_KEY {
+                match bucket.as_array().and_then(|a| a.iter().map(|v| v.as_str().map(str::to_string)).collect()) {
+                    Some(parsed) => aliases = parsed,
+                    None => issues.push(Issue { path: gpath, message: "must be an array of strings".to_string() })
+                }
+                continue;
+            }
+
+            // Structured bucket, with an explicit rule
+            if let Some(bucket) = bucket.as_object() {
+                if let Some(bucket) = Bucket::parse(key, bucket, &gpath, issues) {
+                    rules.push(bucket);
+                }
+                continue;
+            }
+
+            // Grade threshold, given by the key
+            let min_mean = match key.parse::<f64>() {
+                Ok(parsed) if parsed.is_finite() => Some(parsed),
+                _ => {
+                    issues.push(Issue {
+                        path: gpath.clone(),
+                        message: format!("grade \"{key}\" is not a number (or a bucket with a \"{RULE_KEY}\")")
+                    });
+                    None
+                }
+            };
+            let responses = parse_responses(bucket, &gpath, issues);
+            if let (Some(min_mean), Some(responses)) = (min_mean, responses) {
+                if thresholds.iter().any(|(t, _)| *t == min_mean) {
+                    issues.push(Issue { path: gpath, message: format!("grade \"{key}\" is defined more than once") });
+                    continue;
+                }
+                let rule = Rule { mean: Range { min: Some(min_mean), max: None }, ..Default::default() };
+                thresholds.push((min_mean, Bucket { key: key.clone(), path: gpath, rule, responses }));
+            }
+        }
+
+        if rules.is_empty() && thresholds.is_empty() && issues.len() == n_issues {
+            issues.push(Issue { path: path.to_string(), message: "has no grade thresholds".to_string() });
+        }
+
+        if issues.len() > n_issues {
+            return None;
+        }
+
+        thresholds.sort_by(|a, b| b.0.total_cmp(&a.0));
+        rules.extend(thresholds.into_iter().map(|(_, bucket)| bucket));
+        if let Some((min, max)) = uncovered_means(&rules) {
+            issues.push(Issue {
+                path: path.to_string(),
+                message: match max {
+                    Some(max) => format!("has no responses for means from {min} (inclusive) to {max} (exclusive)"),
+                    None => format!("has no responses for means of {min} or more")
+                }
+            });
+            return None;
+        }
+
+        Some(Self { text: text.to_string(), aliases, buckets: rules })
+    }
+
+    /// Returns the first bucket (see [`Question::buckets`]) whose rule matches the ``grades`` of the question.
+    /// For grade thresholds, this is the threshold with the largest minimum that is lower or equal to the mean.
+    pub fn bucket_for(&self, grades: &QuestionRow) -> Option<&Bucket> {
+        self.buckets.iter().find(|b| b.rule.matches(grades))
+    }
+}
+
+
+impl Bucket {
+    fn parse(key: &str, bucket: &sj::Map<String, sj::Value>, path: &str, issues: &mut Vec<Issue>) -> Option<Self> {
+        let n_issues = issues.len();
+        for unknown in bucket.keys().filter(|k| *k != RULE_KEY && *k != RESPONSES_KEY) {
+            issues.push(Issue {
+                path: json_path(path, unknown),
+                message: format!("unknown key (expected \"{RULE_KEY}\" and \"{RESPONSES_KEY}\")")
+            });
+        }
+
+        let rule = match bucket.get(RULE_KEY) {
+            Some(rule) => Rule::parse(rule, &json_path(path, RULE_KEY), issues),
+            None => {
+                issues.push(Issue { path: path.to_string(), message: format!("is missing the \"{RULE_KEY}\" key") });
+                None
+            }
+        };
+        let rpath = json_path(path, RESPONSES_KEY);
+        let responses = match bucket.get(RESPONSES_KEY) {
+            Some(responses) => parse_responses(responses, &rpath, issues),
+            None => {
+                issues.push(Issue { path: path.to_string(), message: format!("is missing the \"{RESPONSES_KEY}\" key") });
+                None
+            }
+        };
+
+        match (rule, responses) {
+            (Some(rule), Some(responses)) if issues.len() == n_issues => {
+                Some(Self { key: key.to_string(), path: rpath, rule, responses })
+            }
+            _ => None
+        }
+    }
+}
+
+
+impl Rule {
+    fn parse(value: &sj::Value, path: &str, issues: &mut Vec<Issue>) -> Option<Self> {
+        let Some(ranges) = value.as_object() else {
+            issues.push(Issue { path: path.to_string(), message: "must be a mapping of value => range".to_string() });
+            return None;
+        };
+
+        let n_issues = issues.len();
+        let mut rule = Self::default();
+        for (name, range) in ranges {
+            let rpath = json_path(path, name);
+            let target = match name.as_str() {
+                RULE_MEAN_KEY => &mut rule.mean,
+                RULE_STD_KEY => &mut rule.std,
+                RULE_COUNT_KEY => &mut rule.count,
+                _ => {
+                    issues.push(Issue {
+                        path: rpath,
+                        message: format!(
+                            "unknown value (expected \"{RULE_MEAN_KEY}\", \"{RULE_STD_KEY}\" or \"{RULE_COUNT_KEY}\")"
+                        )
+                    });
+                    continue;
+                }
+            };
+            if let Some(range) = Range::parse(range, &rpath, issues) {
+                *target = range;
+            }
+        }
+
+        (issues.len() == n_issues).then_some(rule)
+    }
+
+    /// Checks whether the ``grades`` match the rule. A range of the standard deviation or the number
+    /// of respondents does not match when the value is not known.
+    pub fn matches(&self, grades: &QuestionRow) -> bool {
+        self.mean.contains(Some(grades.mean))
+            && self.std.contains(grades.std)
+            && self.count.contains(grades.count.map(|c| c as f64))
+    }
+}
+
+
+impl Range {
+    fn parse(value: &sj::Value, path: &str, issues: &mut Vec<Issue>) -> Option<Self> {
+        let Some(bounds) = value.as_object() else {
+            issues.push(Issue {
+                path: path.to_string(),
+                message: format!("must be a mapping with \"{RANGE_MIN_KEY}\" and/or \"{RANGE_MAX_KEY}\"")
+            });
+            return None;
+        };
+
+        let n_issues = issues.len();
+        let mut range = Self::default();
+        for (name, bound) in bounds {
+            let bpath = json_path(path, name);
+            let target = match name.as_str() {
+                RANGE_MIN_KEY => &mut range.min,
+                RANGE_MAX_KEY => &mut range.max,
+                _ => {
+                    issues.push(Issue {
+                        path: bpath,
+                        message: format!("unknown bound (expected \"{RANGE_MIN_KEY}\" or \"{RANGE_MAX_KEY}\")")
+                    });
+                    continue;
+                }
+            };
+            match bound.as_f64() {
+                Some(bound) => *target = Some(bound),
+                None => issues.push(Issue { path: bpath, message: format!("bound must be a number (found {bound})") })
+            }
+        }
+
+        if let (Some(min), Some(max)) = (range.min, range.max) && min >= max {
+            issues.push(Issue { path: path.to_string(), message: format!("is empty ({RANGE_MIN_KEY} {min} >= {RANGE_MAX_KEY} {max})") });
+        }
+
+        (issues.len() == n_issues).then_some(range)
+    }
+
+    /// Checks whether ``value`` is inside the range. An unknown value is only inside an unbounded range.
+    pub fn contains(&self, value: Option<f64>) -> bool {
+        // Compare scaled integers to prevent influence of numeric error
+        let scaled = |v: f64| (v * 10000.0).round() as i64;
+        match value {
+            Some(value) => {
+                self.min.is_none_or(|min| scaled(min) <= scaled(value))
+                    && self.max.is_none_or(|max| scaled(value) < scaled(max))
+            }
+            None => self.is_unbounded()
+        }
+    }
+
+    fn is_unbounded(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+}
+
+
+/// Returns the first range of means between the lowest and the highest STUDIS grade that is not covered by
+/// the ``buckets`` (its inclusive minimum and its exclusive maximum, if lower or equal to the highest grade).
+/// Only the buckets without a range of the standard deviation or the number of respondents always match.
+fn uncovered_means(buckets: &[Bucket]) -> Option<(f64, Option<f64>)> {
+    let mut ranges: Vec<Range> = buckets.iter()
+        .filter(|b| b.rule.std.is_unbounded() && b.rule.count.is_unbounded())
+        .map(|b| b.rule.mean)
+        .collect();
+    ranges.sort_by(|a, b| a.min.unwrap_or(f64::NEG_INFINITY).total_cmp(&b.min.unwrap_or(f64::NEG_INFINITY)));
+
+    // Means lower than ``covered`` are covered
+    let mut covered = GRADE_MIN;
+    for range in ranges {
+        if covered > GRADE_MAX {
+            break;
+        }
+        match range.min {
+            Some(min) if min > covered => return Some((covered, (min <= GRADE_MAX).then_some(min))),
+            _ => covered = covered.max(range.max.unwrap_or(f64::INFINITY))
+        }
+    }
+    (covered <= GRADE_MAX).then_some((covered, None))
+}
+
+
+fn parse_responses(value: &sj::Value, path: &str, issues: &mut Vec<Issue>) -> Option<Vec<String>> {
+    let Some(array) = value.as_array() else {
+        issues.push(Issue { path: path.to_string(), message: "must be an array of strings".to_string() });
+        return None;
+    };
+
+    if array.is_empty() {
+        issues.push(Issue { path: path.to_string(), message: "has no responses".to_string() });
+        return None;
+    }
+
+    let mut responses = Vec::with_capacity(array.len());
+    for (i, response) in array.iter().enumerate() {
+        match response.as_str() {
+            Some(response) => responses.push(response.to_string()),
+            None => issues.push(Issue { path: format!("{path}[{i}]"), message: format!("response must be a string (found {response})") })
+        }
+    }
+
+    (responses.len() == array.len()).then_some(responses)
+}
+
+
+/// Appends the mapping ``key`` to the JSON ``path``.
+pub(crate) fn json_path(path: &str, key: &str) -> String {
+    format!("{path}[{}]", sj::Value::from(key))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    /// Returns the problems of the responses file, with the questions given by ``questions``.
+    fn issues(questions: sj::Value) -> Vec<String> {
+        match ResponseSet::from_value(&sj::json!({ QUESTIONS_KEY: questions })) {
+            Ok(_) => Vec::new(),
+            Err(Error::ResponseSchema(issues)) => issues.iter().map(|i| i.to_string()).collect(),
+            Err(e) => panic!("unexpected error: {e}")
+        }
+    }
+
+    fn row(mean: f64, std: Option<f64>) -> QuestionRow {
+        QuestionRow { question: "Q".to_string(), count: Some(10), mean, std, decimals: 2 }
+    }
+
+    #[test]
+    fn includes_min_and_excludes_max() {
+        let min = Range { min: Some(4.5), max: None };
+        let max = Range { min: None, max: Some(4.5) };
+        assert!(min.contains(Some(4.5)));
+        assert!(!min.contains(Some(4.49)));
+        assert!(!max.contains(Some(4.5)));
+        assert!(max.contains(Some(4.49)));
+
+        // Numeric error does not move a value across a bound
+        assert!(min.contains(Some(4.5 - 1e-12)));
+        assert!(!max.contains(Some(4.5 - 1e-12)));
+        assert!(Range { min: Some(0.8), max: None }.contains(Some(0.1 + 0.7)));
+
+        assert!(!min.contains(None));
+        assert!(Range::default().contains(None));
+    }
+
+    #[test]
+    fn matches_rules() {
+        let rule = Rule {
+            mean: Range { min: Some(4.5), max: None },
+            std: Range { min: None, max: Some(0.5) },
+            ..Default::default()
+        };
+        assert!(rule.matches(&row(4.5, Some(0.49))));
+        assert!(!rule.matches(&row(4.49, Some(0.49))));
+        assert!(!rule.matches(&row(4.5, Some(0.5))));
+        // The section has no standard deviation
+        assert!(!rule.matches(&row(4.5, None)));
+        assert!(Rule { std: Range::default(), ..rule }.matches(&row(4.5, None)));
+    }
+
+    #[test]
+    fn reads_valid_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "{}", sj::json!({
+            QUESTIONS_KEY: {
+                "Q1": {
+                    "enotno": {"rule": {"mean": {"min": 4.5}, "std": {"max": 0.5}}, "responses": ["Enotno"]},
+                    "1": ["Slabo"],
+                    "4.5": ["Odlično 1", "Odlično 2"],
+                    "aliases": ["Vprašanje 1"]
+                }
+            },
+            "Anketa o predmetu": { QUESTIONS_KEY: { "Q2": { "1": ["Odziv"] } } }
+        })).unwrap();
+
+        let set = ResponseSet::from_file(file.path()).unwrap();
+        let question = &set.section(None, "Anketa o izvajalcu").unwrap().questions[0];
+        assert_eq!(question.aliases, ["Vprašanje 1"]);
+        let keys: Vec<&str> = question.buckets.iter().map(|b| b.key.as_str()).collect();
+        assert_eq!(keys, ["enotno", "4.5", "1"]);
+        assert_eq!(question.buckets[1].path, "$[\"Vprašanje\"][\"Q1\"][\"4.5\"]");
+        assert_eq!(set.section(Some("Anketa o predmetu"), "Anketa o izvajalcu").unwrap().questions[0].text, "Q2");
+    }
+
+    #[test]
+    fn rejects_overlapping_and_uncovered_ranges() {
+        assert_eq!(
+            issues(sj::json!({ "Q": { "1": ["a"], "4": ["b"], "4.0": ["c"] } })),
+            ["$[\"Vprašanje\"][\"Q\"][\"4.0\"]: grade \"4.0\" is defined more than once"]
+        );
+        assert_eq!(
+            issues(sj::json!({ "Q": { "2": ["a"] } })),
+            ["$[\"Vprašanje\"][\"Q\"]: has no responses for means from 1 (inclusive) to 2 (exclusive)"]
+        );
+        assert_eq!(
+            issues(sj::json!({ "Q": { "nizko": {"rule": {"mean": {"max": 4.5}}, "responses": ["a"]} } })),
+            ["$[\"Vprašanje\"][\"Q\"]: has no responses for means of 4.5 or more"]
+        );
+        // Rules on the standard deviation or the number of respondents do not always match
+        assert_eq!(
+            issues(sj::json!({ "Q": { "malo": {"rule": {"count": {"max": 5}}, "responses": ["a"]}, "3": ["b"] } })),
+            ["$[\"Vprašanje\"][\"Q\"]: has no responses for means from 1 (inclusive) to 3 (exclusive)"]
+        );
+        assert!(issues(sj::json!({
+            "Q": { "nizko": {"rule": {"mean": {"max": 3}}, "responses": ["a"]}, "3": ["b"] }
+        })).is_empty());
+    }
+
+    #[test]
+    fn rejects_empty_buckets() {
+        assert_eq!(
+            issues(sj::json!({ "Q1": {}, "Q2": { "1": [] }, "Q3": { "x": {"rule": {}, "responses": []}, "1": ["a"] } })),
+            [
+                "$[\"Vprašanje\"][\"Q1\"]: has no grade thresholds",
+                "$[\"Vprašanje\"][\"Q2\"][\"1\"]: has no responses",
+                "$[\"Vprašanje\"][\"Q3\"][\"x\"][\"responses\"]: has no responses"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bounds() {
+        let rule = |rule: sj::Value| issues(sj::json!({ "Q": { "x": {"rule": rule, "responses": ["a"]}, "1": ["b"] } }));
+        assert_eq!(
+            rule(sj::json!({"mean": {"min": "4"}})),
+            ["$[\"Vprašanje\"][\"Q\"][\"x\"][\"rule\"][\"mean\"][\"min\"]: bound must be a number (found \"4\")"]
+        );
+        assert_eq!(
+            rule(sj::json!({"std": {"min": 1, "max": 0.5}})),
+            ["$[\"Vprašanje\"][\"Q\"][\"x\"][\"rule\"][\"std\"]: is empty (min 1 >= max 0.5)"]
+        );
+        assert_eq!(
+            rule(sj::json!({"count": {"from": 5}})),
+            ["$[\"Vprašanje\"][\"Q\"][\"x\"][\"rule\"][\"count\"][\"from\"]: unknown bound (expected \"min\" or \"max\")"]
+        );
+        assert_eq!(
+            rule(sj::json!({"mean": 4})),
+            ["$[\"Vprašanje\"][\"Q\"][\"x\"][\"rule\"][\"mean\"]: must be a mapping with \"min\" and/or \"max\""]
+        );
+        assert_eq!(
+            issues(sj::json!({ "Q": { "4,5": ["a"] } })),
+            ["$[\"Vprašanje\"][\"Q\"][\"4,5\"]: grade \"4,5\" is not a number (or a bucket with a \"rule\")"]
+        );
+    }
+}